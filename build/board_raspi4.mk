@@ -0,0 +1,10 @@
+TARGET            = aarch64-unknown-none-softfloat
+KERNEL_BIN        = target/img/kernel-aarch64-raspi4.img
+OBJDUMP_BINARY    = aarch64-none-elf-objdump
+NM_BINARY         = aarch64-none-elf-nm
+READELF_BINARY    = aarch64-none-elf-readelf
+LD_SCRIPT_PATH    = $(shell pwd)/crates/em-kernel/src/board/raspi
+LD_SCRIPT         = kernel_raspi.ld
+RUSTC_MISC_ARGS   = -C target-cpu=cortex-a72
+
+export LD_SCRIPT_PATH
\ No newline at end of file
@@ -1,22 +1,30 @@
 use core::arch::global_asm;
 
-use aarch64_cpu::{asm, registers::{CNTHCTL_EL2, HCR_EL2, CNTVOFF_EL2, SPSR_EL2, ELR_EL2, SP_EL1}};
+use aarch64_cpu::{asm, registers::{CNTHCTL_EL2, HCR_EL2, CNTVOFF_EL2, SPSR_EL2, ELR_EL2, SP_EL1, TPIDR_EL1}};
 use tock_registers::interfaces::Writeable;
 
+use crate::{arch, board};
+
 global_asm!(
     include_str!("boot.s"),
     CONST_CURRENTEL_EL2 = const 0x8,
-    CONST_CORE_ID_MASK = const 0b11
+    CONST_CORE_ID_MASK = const 0b11,
+    CONST_SECONDARY_CORE_STACK_STRIDE = const board::cpu::STACK_GUARD_SIZE + board::cpu::SECONDARY_CORE_STACK_SIZE,
+    KERNEL_ENTRY = sym crate::kenter,
+    SECONDARY_ENTRY = sym crate::ksecondary
 );
 
 #[inline(always)]
-unsafe fn prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr: u64) {
+unsafe fn prepare_el2_to_el1_transition(virt_stack_end_exclusive_addr: u64, virt_entry_addr: u64) {
     // Enable timer counter registers for EL1.
     CNTHCTL_EL2.write(CNTHCTL_EL2::EL1PCEN::SET + CNTHCTL_EL2::EL1PCTEN::SET);
 
     // No offset for reading the counters.
     CNTVOFF_EL2.set(0);
 
+    // Per-CPU variables refer to their templates until the core's per-CPU area is set up.
+    TPIDR_EL1.set(0);
+
     // Set EL1 execution state to AArch64.
     HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
@@ -32,16 +40,34 @@ unsafe fn prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr:
             + SPSR_EL2::M::EL1h,
     );
 
-    // Second, let the link register point to kernel_init().
-    ELR_EL2.set(crate::kenter as *const () as u64);
+    // Second, let the link register point to the kernel entry point. Taking the address of a
+    // function here would give its physical address, so the boot code passes the linked one.
+    ELR_EL2.set(virt_entry_addr);
 
     // Set up SP_EL1 (stack pointer), which will be used by EL1 once we "return" to it. Since there
-    // are no plans to ever return to EL2, just re-use the same stack.
-    SP_EL1.set(phys_boot_core_stack_end_exclusive_addr);
+    // are no plans to ever return to EL2, just re-use the same stack, at its linked address.
+    SP_EL1.set(virt_stack_end_exclusive_addr);
 }
 
-#[no_mangle]
-pub unsafe fn _enter_kernel(phys_boot_core_stack_end_exclusive_addr: u64) -> ! {
-    prepare_el2_to_el1_transition(phys_boot_core_stack_end_exclusive_addr);
+/// Drops to EL1, where the kernel runs at its linked addresses in the upper half.
+#[inline(always)]
+unsafe fn enter_el1(virt_stack_end_exclusive_addr: u64, virt_entry_addr: u64) -> ! {
+    prepare_el2_to_el1_transition(virt_stack_end_exclusive_addr, virt_entry_addr);
+
+    // Nothing can be reported this early, so a core that can't run the kernel is parked.
+    if arch::memory::enable_boot_mapping().is_err() {
+        arch::cpu::halt();
+    }
+
     asm::eret();
+}
+
+#[no_mangle]
+pub unsafe fn _enter_kernel(virt_boot_core_stack_end_exclusive_addr: u64, virt_entry_addr: u64) -> ! {
+    enter_el1(virt_boot_core_stack_end_exclusive_addr, virt_entry_addr)
+}
+
+#[no_mangle]
+pub unsafe fn _enter_kernel_secondary(virt_stack_end_exclusive_addr: u64, virt_entry_addr: u64) -> ! {
+    enter_el1(virt_stack_end_exclusive_addr, virt_entry_addr)
 }
\ No newline at end of file
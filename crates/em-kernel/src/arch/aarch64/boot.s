@@ -20,6 +20,20 @@
 	add	\register, \register, #:lo12:\symbol
 .endm
 
+// Load the address of a symbol into a register, absolute.
+//
+// Gives the address the symbol is linked at, while the code runs from its physical load address.
+//
+// # Resources
+//
+// - https://sourceware.org/binutils/docs-2.36/as/AArch64_002dRelocations.html
+.macro ADR_ABS register, symbol
+	movz	\register, #:abs_g3:\symbol
+	movk	\register, #:abs_g2_nc:\symbol
+	movk	\register, #:abs_g1_nc:\symbol
+	movk	\register, #:abs_g0_nc:\symbol
+.endm
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -56,6 +70,7 @@ _start:
 	// Prepare the jump to Rust code.
 .L_prepare_rust:
 	// Set the stack pointer. This ensures that any code in EL2 that needs the stack will work.
+	// EL2 runs without the MMU, so the stack is addressed physically.
 	ADR_REL	x0, __boot_core_stack_end_exclusive
 	mov	sp, x0
 
@@ -67,7 +82,10 @@ _start:
 	b.eq	.L_parking_loop
 	str	w2, [x1]
 
-	// Jump to Rust code. x0 holds the function argument provided to _enter_kernel().
+	// Jump to Rust code. x0 and x1 hold the function arguments provided to _enter_kernel(): the
+	// stack and the entry point of EL1, at their linked addresses.
+	ADR_ABS	x0, __boot_core_stack_end_exclusive
+	ADR_ABS	x1, {KERNEL_ENTRY}
 	b	_enter_kernel
 
 	// Infinitely wait for events (aka "park the core").
@@ -77,4 +95,41 @@ _start:
 
 .size	_start, . - _start
 .type	_start, function
-.global	_start
\ No newline at end of file
+.global	_start
+
+//------------------------------------------------------------------------------
+// fn _start_secondary()
+//
+// Entry point of the secondary cores, once they are released from the spin table.
+//------------------------------------------------------------------------------
+_start_secondary:
+	// Only proceed if the core executes in EL2. Park it otherwise.
+	mrs	x0, CurrentEL
+	cmp	x0, {CONST_CURRENTEL_EL2}
+	b.ne	.L_secondary_parking_loop
+
+	// Core N uses the (N - 1)th secondary core stack. Each stack has a guard below it, so the
+	// stack ends at start + N * (guard size + stack size). EL2 runs without the MMU, so the stack
+	// is addressed physically.
+	mrs	x1, MPIDR_EL1
+	and	x1, x1, {CONST_CORE_ID_MASK}
+	ADR_REL	x0, __secondary_core_stacks_start
+	mov	x2, {CONST_SECONDARY_CORE_STACK_STRIDE}
+	madd	x0, x1, x2, x0
+	mov	sp, x0
+
+	// Jump to Rust code. x0 and x1 hold the function arguments provided to
+	// _enter_kernel_secondary(): the same stack and the entry point of EL1, at their linked
+	// addresses.
+	ADR_ABS	x0, __secondary_core_stacks_start
+	madd	x0, x1, x2, x0
+	ADR_ABS	x1, {SECONDARY_ENTRY}
+	b	_enter_kernel_secondary
+
+.L_secondary_parking_loop:
+	wfe
+	b	.L_secondary_parking_loop
+
+.size	_start_secondary, . - _start_secondary
+.type	_start_secondary, function
+.global	_start_secondary
\ No newline at end of file
@@ -1,7 +1,28 @@
-use aarch64_cpu;
+use aarch64_cpu::{self, registers::{MPIDR_EL1, SP}};
+use tock_registers::interfaces::Readable;
 
 pub use aarch64_cpu::asm::nop;
 
+/// The ID of the executing core, as given by affinity level 0 of `MPIDR_EL1`.
+#[inline(always)]
+pub fn core_id() -> usize {
+    (MPIDR_EL1.get() & 0xFF) as usize
+}
+
+/// The stack pointer of the executing core.
+#[inline(always)]
+pub fn stack_pointer() -> usize {
+    SP.get() as usize
+}
+
+/// Park the executing core until an interrupt is pending.
+///
+/// Pending IRQs wake the core even if they are masked.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    aarch64_cpu::asm::wfi();
+}
+
 #[inline(always)]
 pub fn halt() -> ! {
     loop {
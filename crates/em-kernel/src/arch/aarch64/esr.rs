@@ -0,0 +1,171 @@
+use core::fmt;
+
+use aarch64_cpu::registers::ESR_EL1;
+use tock_registers::{interfaces::Readable, register_bitfields, registers::InMemoryRegister};
+
+use crate::exception::{AccessType, Fault, FaultKind};
+
+// The instruction specific syndrome of instruction and data aborts, as per ARMv8-A Architecture
+// Reference Manual sections D17.2.37 ("ISS encoding for an exception from an Instruction Abort")
+// and D17.2.38 ("ISS encoding for an exception from a Data Abort").
+register_bitfields! {u64,
+    ABORT_ISS [
+        /// FAR not Valid. Only meaningful for synchronous external aborts.
+        FnV OFFSET(10) NUMBITS(1) [],
+
+        /// Write not Read. Only present in data aborts.
+        WnR OFFSET(6) NUMBITS(1) [
+            Read = 0,
+            Write = 1
+        ],
+
+        /// Data/Instruction Fault Status Code.
+        FSC OFFSET(0) NUMBITS(6) []
+    ]
+}
+
+/// The decoded cause of a synchronous exception, given by the exception class of `ESR_EL1`.
+#[derive(Copy, Clone)]
+pub enum ExceptionCause {
+    /// The exception class is not known, usually because an undefined instruction was executed.
+    Unknown,
+
+    /// A `WFI` or `WFE` instruction was trapped.
+    TrappedWfiWfe,
+
+    /// Exception return to an illegal execution state.
+    IllegalExecutionState,
+
+    /// A supervisor call with the given immediate.
+    SupervisorCall(u16),
+
+    /// A hypervisor call with the given immediate.
+    HypervisorCall(u16),
+
+    /// A secure monitor call with the given immediate.
+    SecureMonitorCall(u16),
+
+    /// A trapped `MSR`, `MRS` or system instruction.
+    TrappedSystemRegister,
+
+    /// An instruction fetch faulted.
+    InstructionAbort(Fault),
+
+    /// A data access faulted.
+    DataAbort(Fault),
+
+    /// The program counter was misaligned.
+    PcAlignment(usize),
+
+    /// The stack pointer was misaligned.
+    SpAlignment,
+
+    /// An asynchronous system error.
+    SError,
+
+    /// A hardware breakpoint, software step or watchpoint debug exception.
+    Debug,
+
+    /// A `BRK` instruction with the given immediate.
+    Breakpoint(u16),
+
+    /// An exception class the kernel does not decode.
+    Other(u8),
+}
+
+impl ExceptionCause {
+    /// Decodes the exception syndrome, using the fault address register where applicable.
+    pub fn decode(esr: &InMemoryRegister<u64, ESR_EL1::Register>, far: u64) -> Self {
+        let iss = esr.read(ESR_EL1::ISS);
+        let imm16 = (iss & 0xFFFF) as u16;
+
+        use ESR_EL1::EC::Value::*;
+        match esr.read_as_enum(ESR_EL1::EC) {
+            Some(Unknown) => Self::Unknown,
+            Some(TrappedWFIorWFE) => Self::TrappedWfiWfe,
+            Some(IllegalExecutionState) => Self::IllegalExecutionState,
+            Some(SVC64) => Self::SupervisorCall(imm16),
+            Some(HVC64) => Self::HypervisorCall(imm16),
+            Some(SMC64) => Self::SecureMonitorCall(imm16),
+            Some(TrappedMsrMrs) => Self::TrappedSystemRegister,
+            Some(InstrAbortLowerEL) | Some(InstrAbortCurrentEL) => {
+                Self::InstructionAbort(decode_abort(iss, far, AccessType::Execute))
+            }
+            Some(DataAbortLowerEL) | Some(DataAbortCurrentEL) => {
+                let iss_reg = InMemoryRegister::<u64, ABORT_ISS::Register>::new(iss);
+                let access = match iss_reg.read_as_enum(ABORT_ISS::WnR) {
+                    Some(ABORT_ISS::WnR::Value::Write) => AccessType::Write,
+                    _ => AccessType::Read,
+                };
+                Self::DataAbort(decode_abort(iss, far, access))
+            }
+            Some(PCAlignmentFault) => Self::PcAlignment(far as usize),
+            Some(SPAlignmentFault) => Self::SpAlignment,
+            Some(SError) => Self::SError,
+            Some(BreakpointLowerEL)
+            | Some(BreakpointCurrentEL)
+            | Some(SoftwareStepLowerEL)
+            | Some(SoftwareStepCurrentEL)
+            | Some(WatchpointLowerEL)
+            | Some(WatchpointCurrentEL) => Self::Debug,
+            Some(Brk64) => Self::Breakpoint(imm16),
+            _ => Self::Other(esr.read(ESR_EL1::EC) as u8),
+        }
+    }
+}
+
+impl fmt::Display for ExceptionCause {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unknown => write!(f, "Unknown reason (undefined instruction?)"),
+            Self::TrappedWfiWfe => write!(f, "Trapped WFI/WFE"),
+            Self::IllegalExecutionState => write!(f, "Illegal execution state"),
+            Self::SupervisorCall(imm) => write!(f, "SVC #{:#x}", imm),
+            Self::HypervisorCall(imm) => write!(f, "HVC #{:#x}", imm),
+            Self::SecureMonitorCall(imm) => write!(f, "SMC #{:#x}", imm),
+            Self::TrappedSystemRegister => write!(f, "Trapped MSR, MRS or system instruction"),
+            Self::InstructionAbort(fault) | Self::DataAbort(fault) => write!(f, "{}", fault),
+            Self::PcAlignment(pc) => write!(f, "PC alignment fault at {:#018x}", pc),
+            Self::SpAlignment => write!(f, "SP alignment fault"),
+            Self::SError => write!(f, "SError"),
+            Self::Debug => write!(f, "Debug exception"),
+            Self::Breakpoint(imm) => write!(f, "BRK #{:#x}", imm),
+            Self::Other(ec) => write!(f, "Exception class {:#x}", ec),
+        }
+    }
+}
+
+/// Decodes the fault status code shared by instruction and data aborts.
+fn decode_abort(iss: u64, far: u64, access: AccessType) -> Fault {
+    let iss = InMemoryRegister::<u64, ABORT_ISS::Register>::new(iss);
+    let fsc = iss.read(ABORT_ISS::FSC) as u8;
+    let level = fsc & 0b11;
+
+    let (kind, level) = match fsc {
+        0b00_0000..=0b00_0011 => (FaultKind::AddressSize, Some(level)),
+        0b00_0100..=0b00_0111 => (FaultKind::Translation, Some(level)),
+        0b00_1001..=0b00_1011 => (FaultKind::AccessFlag, Some(level)),
+        0b00_1101..=0b00_1111 => (FaultKind::Permission, Some(level)),
+        0b01_0000 | 0b01_1000 => (FaultKind::External, None),
+        0b01_0100..=0b01_0111 | 0b01_1100..=0b01_1111 => (FaultKind::External, Some(level)),
+        0b10_0001 => (FaultKind::Alignment, None),
+        0b11_0000 => (FaultKind::TlbConflict, None),
+        0b11_0101 => (FaultKind::UnsupportedAtomic, None),
+        _ => (FaultKind::Unknown, None),
+    };
+
+    // The FAR is always valid for translation, permission and alignment faults. It is only
+    // unknown for external aborts that explicitly flag it.
+    let address = if kind == FaultKind::External && iss.is_set(ABORT_ISS::FnV) {
+        None
+    } else {
+        Some(far as usize)
+    };
+
+    Fault {
+        kind,
+        access,
+        address,
+        level,
+    }
+}
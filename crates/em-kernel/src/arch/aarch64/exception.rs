@@ -1,7 +1,283 @@
-use aarch64_cpu::registers::*;
-use tock_registers::interfaces::Readable;
+use core::{arch::global_asm, cell::UnsafeCell, fmt};
 
-use crate::exception::{PrivilegeLevel, PrivilegeKind};
+use aarch64_cpu::{asm::barrier, registers::*};
+use tock_registers::{interfaces::{Readable, Writeable}, registers::InMemoryRegister};
+
+use crate::{
+    board,
+    exception::{InterruptedState, PrivilegeLevel, PrivilegeKind},
+    memory,
+};
+
+use super::esr::ExceptionCause;
+
+// Assembly counterpart to this file.
+global_asm!(
+    include_str!("exception.s"),
+    CONST_CORE_ID_MASK = const 0b11,
+    CONST_OVERFLOW_STACK_SHIFT = const OVERFLOW_STACK_SIZE.trailing_zeros(),
+    OVERFLOW_STACKS = sym OVERFLOW_STACKS
+);
+
+/// The size of the stack a core handles exceptions on once its kernel stack overflowed.
+const OVERFLOW_STACK_SIZE: usize = 16 * 1024;
+
+const _: () = assert!(OVERFLOW_STACK_SIZE.is_power_of_two());
+
+#[repr(C, align(16))]
+struct OverflowStacks([[u8; OVERFLOW_STACK_SIZE]; board::cpu::NUM_CORES]);
+
+/// The overflow stacks of all cores, switched to by `exception.s`.
+static mut OVERFLOW_STACKS: OverflowStacks = OverflowStacks([[0; OVERFLOW_STACK_SIZE]; board::cpu::NUM_CORES]);
+
+/// Wrapper struct for memory copies of registers.
+#[repr(transparent)]
+struct SpsrEL1(InMemoryRegister<u64, SPSR_EL1::Register>);
+
+#[repr(transparent)]
+struct EsrEL1(InMemoryRegister<u64, ESR_EL1::Register>);
+
+/// The exception context as it is stored on the stack on exception entry.
+///
+/// The layout must match the one produced by `CALL_WITH_CONTEXT` in `exception.s`.
+#[repr(C)]
+pub struct ExceptionContext {
+    /// General Purpose Registers x0-x30. x29 is the frame pointer, x30 the link register.
+    gpr: [u64; 31],
+
+    /// Exception link register. The program counter at the time the exception happened.
+    elr_el1: u64,
+
+    /// Saved program status.
+    spsr_el1: SpsrEL1,
+
+    /// Exception syndrome register.
+    esr_el1: EsrEL1,
+
+    /// Fault address register.
+    far_el1: u64,
+
+    /// Keeps the context a multiple of 16 bytes, so the stack stays aligned.
+    _padding: u64,
+}
+
+const _: () = assert!(core::mem::size_of::<ExceptionContext>() == 16 * 18);
+
+impl ExceptionContext {
+    /// Decodes the syndrome of the exception that produced this context.
+    pub fn cause(&self) -> ExceptionCause {
+        ExceptionCause::decode(&self.esr_el1.0, self.far_el1)
+    }
+
+    /// What the core was executing when it took the exception.
+    pub fn interrupted_state(&self) -> InterruptedState {
+        let privilege_level = match self.spsr_el1.0.read_as_enum(SPSR_EL1::M) {
+            Some(SPSR_EL1::M::Value::EL0t) => PrivilegeLevel::new(PrivilegeKind::User, "EL0"),
+            Some(SPSR_EL1::M::Value::EL1t | SPSR_EL1::M::Value::EL1h) => {
+                PrivilegeLevel::new(PrivilegeKind::Kernel, "EL1")
+            }
+            _ => PrivilegeLevel::new(PrivilegeKind::Unknown, "Unknown"),
+        };
+
+        InterruptedState {
+            pc: self.elr_el1 as usize,
+            privilege_level,
+        }
+    }
+}
+
+/// Prints the exception context with the details of the exception that caused it.
+impl fmt::Display for ExceptionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "ESR_EL1: {:#010x}", self.esr_el1.0.get())?;
+        writeln!(
+            f,
+            "      Exception Class         (EC) : {:#x} - {}",
+            self.esr_el1.0.read(ESR_EL1::EC),
+            self.cause()
+        )?;
+        writeln!(f, "      Instr Specific Syndrome (ISS): {:#x}", self.esr_el1.0.read(ESR_EL1::ISS))?;
+        writeln!(f, "FAR_EL1: {:#018x}", self.far_el1)?;
+        writeln!(f, "{}", self.spsr_el1)?;
+        writeln!(f, "ELR_EL1: {:#018x}", self.elr_el1)?;
+        writeln!(f)?;
+        writeln!(f, "General purpose registers:")?;
+
+        let alternating = |x| -> &str {
+            if x % 2 == 0 {
+                "   "
+            } else {
+                "\n"
+            }
+        };
+
+        for (i, reg) in self.gpr.iter().enumerate() {
+            write!(f, "      x{: <2}: {: >#018x}{}", i, reg, alternating(i))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for SpsrEL1 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "SPSR_EL1: {:#010x}", self.0.get())?;
+
+        let to_flag_str = |x| -> &str {
+            if x {
+                "Set"
+            } else {
+                "Not set"
+            }
+        };
+
+        writeln!(f, "      Flags:")?;
+        writeln!(f, "            Negative (N): {}", to_flag_str(self.0.is_set(SPSR_EL1::N)))?;
+        writeln!(f, "            Zero     (Z): {}", to_flag_str(self.0.is_set(SPSR_EL1::Z)))?;
+        writeln!(f, "            Carry    (C): {}", to_flag_str(self.0.is_set(SPSR_EL1::C)))?;
+        writeln!(f, "            Overflow (V): {}", to_flag_str(self.0.is_set(SPSR_EL1::V)))?;
+
+        let to_mask_str = |x| -> &str {
+            if x {
+                "Masked"
+            } else {
+                "Unmasked"
+            }
+        };
+
+        writeln!(f, "      Exception handling state:")?;
+        writeln!(f, "            Debug  (D): {}", to_mask_str(self.0.is_set(SPSR_EL1::D)))?;
+        writeln!(f, "            SError (A): {}", to_mask_str(self.0.is_set(SPSR_EL1::A)))?;
+        writeln!(f, "            IRQ    (I): {}", to_mask_str(self.0.is_set(SPSR_EL1::I)))?;
+        writeln!(f, "            FIQ    (F): {}", to_mask_str(self.0.is_set(SPSR_EL1::F)))?;
+
+        write!(
+            f,
+            "      Illegal Execution State (IL): {}",
+            to_flag_str(self.0.is_set(SPSR_EL1::IL))
+        )
+    }
+}
+
+/// The handler for any exception the kernel does not expect. Prints the context and panics.
+#[inline(never)]
+fn default_exception_handler(kind: &str, ctx: &ExceptionContext) -> ! {
+    panic!("CPU Exception: {}\n  {}\n\n{}", kind, ctx.cause(), ctx);
+}
+
+//------------------------------------------------------------------------------
+// Current, EL0
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_el0_synchronous(ctx: &mut ExceptionContext) {
+    default_exception_handler("current EL0, synchronous", ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler("current EL0, IRQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler("current EL0, FIQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_el0_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler("current EL0, SError", ctx);
+}
+
+//------------------------------------------------------------------------------
+// Current, ELx
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn current_elx_synchronous(ctx: &mut ExceptionContext) {
+    if let ExceptionCause::DataAbort(fault) = ctx.cause() {
+        if let Some(core) = fault.address.and_then(memory::stack::guard_owner) {
+            panic!("Kernel stack overflow on core {}\n  {}\n\n{}", core, ctx.cause(), ctx);
+        }
+
+        // The access is retried once the memory is populated.
+        if memory::region::handle_fault(&fault) {
+            return;
+        }
+    }
+
+    default_exception_handler("current ELx, synchronous", ctx);
+}
+
+/// Handles a synchronous exception taken with too little room left on the kernel stack, on the
+/// overflow stack. The interrupted stack pointer wasn't kept, so the exception can't return.
+#[no_mangle]
+extern "C" fn current_elx_stack_overflow(ctx: &mut ExceptionContext) -> ! {
+    panic!("Kernel stack overflow on core {}\n  {}\n\n{}", super::cpu::core_id(), ctx.cause(), ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_irq(ctx: &mut ExceptionContext) {
+    crate::interrupt::dispatch(ctx.interrupted_state());
+}
+
+#[no_mangle]
+extern "C" fn current_elx_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler("current ELx, FIQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn current_elx_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler("current ELx, SError", ctx);
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch64
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch64_synchronous(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch64, synchronous", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch64, IRQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch64, FIQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch64_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch64, SError", ctx);
+}
+
+//------------------------------------------------------------------------------
+// Lower, AArch32
+//------------------------------------------------------------------------------
+
+#[no_mangle]
+extern "C" fn lower_aarch32_synchronous(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch32, synchronous", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_irq(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch32, IRQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_fiq(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch32, FIQ", ctx);
+}
+
+#[no_mangle]
+extern "C" fn lower_aarch32_serror(ctx: &mut ExceptionContext) {
+    default_exception_handler("lower EL, AArch32, SError", ctx);
+}
 
 pub fn current_privilege_level() -> PrivilegeLevel {
     let el = CurrentEL.read_as_enum(CurrentEL::EL);
@@ -23,4 +299,22 @@ pub fn current_privilege_level() -> PrivilegeLevel {
             "Unknown",
         ),
     }
-}
\ No newline at end of file
+}
+
+/// Installs the exception vector table by pointing `VBAR_EL1` at it.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+/// - The vector table and the symbol `__exception_vector_start` are defined in `exception.s`.
+pub unsafe fn handling_init() {
+    // Provided by exception.s.
+    extern "Rust" {
+        static __exception_vector_start: UnsafeCell<()>;
+    }
+
+    VBAR_EL1.set(__exception_vector_start.get() as u64);
+
+    // Force VBAR update to complete before next instruction.
+    barrier::isb(barrier::SY);
+}
@@ -0,0 +1,196 @@
+// Based on: https://github.com/rust-embedded/rust-raspberrypi-OS-tutorials/blob/ac3ea0e616ccf1da6ac83206490e50034ee35072/11_exceptions_part1_groundwork/src/_arch/aarch64/exception.s
+
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2018-2022 Andre Richter <andre.o.richter@gmail.com>
+
+//--------------------------------------------------------------------------------------------------
+// Definitions
+//--------------------------------------------------------------------------------------------------
+
+// The size of the saved ExceptionContext, rounded up to keep the stack 16 byte aligned.
+.equ CONTEXT_SIZE, 16 * 18
+
+/// Call the function provided by parameter `\handler` after saving the exception context. Provide
+/// the context as the first parameter to '\handler'.
+.macro CALL_WITH_CONTEXT handler
+__vector_\handler:
+	// Make room on the stack for the exception context.
+	sub	sp,  sp,  #CONTEXT_SIZE
+
+	// Store all general purpose registers on the stack.
+	stp	x0,  x1,  [sp, #16 * 0]
+	stp	x2,  x3,  [sp, #16 * 1]
+	stp	x4,  x5,  [sp, #16 * 2]
+	stp	x6,  x7,  [sp, #16 * 3]
+	stp	x8,  x9,  [sp, #16 * 4]
+	stp	x10, x11, [sp, #16 * 5]
+	stp	x12, x13, [sp, #16 * 6]
+	stp	x14, x15, [sp, #16 * 7]
+	stp	x16, x17, [sp, #16 * 8]
+	stp	x18, x19, [sp, #16 * 9]
+	stp	x20, x21, [sp, #16 * 10]
+	stp	x22, x23, [sp, #16 * 11]
+	stp	x24, x25, [sp, #16 * 12]
+	stp	x26, x27, [sp, #16 * 13]
+	stp	x28, x29, [sp, #16 * 14]
+
+	// Add the exception link register (ELR_EL1), saved program status (SPSR_EL1), exception
+	// syndrome register (ESR_EL1) and fault address register (FAR_EL1).
+	mrs	x1,  ELR_EL1
+	mrs	x2,  SPSR_EL1
+	mrs	x3,  ESR_EL1
+	mrs	x4,  FAR_EL1
+
+	stp	x30, x1,  [sp, #16 * 15]
+	stp	x2,  x3,  [sp, #16 * 16]
+	stp	x4,  xzr, [sp, #16 * 17]
+
+	// x0 is the first argument for the function called through `\handler`.
+	mov	x0,  sp
+
+	// Call `\handler`.
+	bl	\handler
+
+	// After returning from exception handling code, replay the saved context and return via
+	// `eret`.
+	b	__exception_restore_context
+
+.size	__vector_\handler, . - __vector_\handler
+.type	__vector_\handler, function
+.endm
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+.section .text
+
+//------------------------------------------------------------------------------
+// The exception vector table.
+//------------------------------------------------------------------------------
+
+// Align by 2^11 bytes, as demanded by ARMv8-A. Same as ALIGN(2048) in an ld script.
+.align 11
+
+// Export a symbol for the Rust code to use.
+.global __exception_vector_start
+__exception_vector_start:
+
+// Current exception level with SP_EL0.
+//
+// .org sets the offset relative to section start.
+//
+// # Safety
+//
+// - It must be ensured that `CALL_WITH_CONTEXT` <= 0x80 bytes.
+.org 0x000
+	CALL_WITH_CONTEXT current_el0_synchronous
+.org 0x080
+	CALL_WITH_CONTEXT current_el0_irq
+.org 0x100
+	CALL_WITH_CONTEXT current_el0_fiq
+.org 0x180
+	CALL_WITH_CONTEXT current_el0_serror
+
+// Current exception level with SP_ELx, x > 0.
+.org 0x200
+	b	__vector_current_elx_synchronous_check_stack
+.org 0x280
+	CALL_WITH_CONTEXT current_elx_irq
+.org 0x300
+	CALL_WITH_CONTEXT current_elx_fiq
+.org 0x380
+	CALL_WITH_CONTEXT current_elx_serror
+
+// Lower exception level, AArch64
+.org 0x400
+	CALL_WITH_CONTEXT lower_aarch64_synchronous
+.org 0x480
+	CALL_WITH_CONTEXT lower_aarch64_irq
+.org 0x500
+	CALL_WITH_CONTEXT lower_aarch64_fiq
+.org 0x580
+	CALL_WITH_CONTEXT lower_aarch64_serror
+
+// Lower exception level, AArch32
+.org 0x600
+	CALL_WITH_CONTEXT lower_aarch32_synchronous
+.org 0x680
+	CALL_WITH_CONTEXT lower_aarch32_irq
+.org 0x700
+	CALL_WITH_CONTEXT lower_aarch32_fiq
+.org 0x780
+	CALL_WITH_CONTEXT lower_aarch32_serror
+.org 0x800
+
+//------------------------------------------------------------------------------
+// Synchronous exceptions of the kernel, which include faults on its own stack.
+//------------------------------------------------------------------------------
+__vector_current_elx_synchronous_check_stack:
+	// A stack overflow faults in the guard below the stack, where the context can't be saved.
+	// Check that the stack has room for it. x0 is kept in SP_EL0 meanwhile, which the kernel
+	// doesn't use while it runs on SP_EL1.
+	msr	SP_EL0, x0
+	sub	x0, sp, #CONTEXT_SIZE
+	at	s1e1w, x0
+	isb
+	mrs	x0, PAR_EL1
+	tbz	x0, #0, .L_stack_checked
+
+	// Switch to the overflow stack of the core N, which ends at start + (N + 1) * size. The
+	// interrupted stack pointer is lost, so the handler never returns.
+	mrs	x0, MPIDR_EL1
+	and	x0, x0, {CONST_CORE_ID_MASK}
+	add	x0, x0, #1
+	lsl	x0, x0, {CONST_OVERFLOW_STACK_SHIFT}
+	mov	sp, x0
+	adrp	x0, {OVERFLOW_STACKS}
+	add	x0, x0, #:lo12:{OVERFLOW_STACKS}
+	add	sp, sp, x0
+	mrs	x0, SP_EL0
+	b	__vector_current_elx_stack_overflow
+
+.L_stack_checked:
+	mrs	x0, SP_EL0
+	b	__vector_current_elx_synchronous
+
+.size	__vector_current_elx_synchronous_check_stack, . - __vector_current_elx_synchronous_check_stack
+.type	__vector_current_elx_synchronous_check_stack, function
+
+	CALL_WITH_CONTEXT current_elx_synchronous
+	CALL_WITH_CONTEXT current_elx_stack_overflow
+
+//------------------------------------------------------------------------------
+// fn __exception_restore_context()
+//------------------------------------------------------------------------------
+__exception_restore_context:
+	// Handlers may have changed the return address or program status, so restore them from the
+	// saved context rather than trusting the live registers.
+	ldr	x19, [sp, #16 * 16]
+	ldp	x30, x20, [sp, #16 * 15]
+
+	msr	SPSR_EL1, x19
+	msr	ELR_EL1,  x20
+
+	ldp	x0,  x1,  [sp, #16 * 0]
+	ldp	x2,  x3,  [sp, #16 * 1]
+	ldp	x4,  x5,  [sp, #16 * 2]
+	ldp	x6,  x7,  [sp, #16 * 3]
+	ldp	x8,  x9,  [sp, #16 * 4]
+	ldp	x10, x11, [sp, #16 * 5]
+	ldp	x12, x13, [sp, #16 * 6]
+	ldp	x14, x15, [sp, #16 * 7]
+	ldp	x16, x17, [sp, #16 * 8]
+	ldp	x18, x19, [sp, #16 * 9]
+	ldp	x20, x21, [sp, #16 * 10]
+	ldp	x22, x23, [sp, #16 * 11]
+	ldp	x24, x25, [sp, #16 * 12]
+	ldp	x26, x27, [sp, #16 * 13]
+	ldp	x28, x29, [sp, #16 * 14]
+
+	add	sp,  sp,  #CONTEXT_SIZE
+
+	eret
+
+.size	__exception_restore_context, . - __exception_restore_context
+.type	__exception_restore_context, function
@@ -0,0 +1,65 @@
+use core::arch::asm;
+
+use aarch64_cpu::registers::DAIF;
+use tock_registers::interfaces::{Readable, Writeable};
+
+/// Bits of the `DAIFSet`/`DAIFClr` immediate.
+mod daif_bits {
+    pub const IRQ: u8 = 0b0010;
+}
+
+/// The IRQ mask state of a core, as saved by [`local_irq_mask_save`].
+#[derive(Copy, Clone)]
+pub struct IrqState(u64);
+
+impl IrqState {
+    /// Whether IRQs were masked when the state was saved.
+    pub fn is_masked(&self) -> bool {
+        self.0 & DAIF::I::SET.value != 0
+    }
+}
+
+/// Unmask IRQs on the executing core.
+#[inline(always)]
+pub fn local_irq_unmask() {
+    unsafe {
+        asm!(
+            "msr DAIFClr, {arg}",
+            arg = const daif_bits::IRQ,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Mask IRQs on the executing core.
+#[inline(always)]
+pub fn local_irq_mask() {
+    unsafe {
+        asm!(
+            "msr DAIFSet, {arg}",
+            arg = const daif_bits::IRQ,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Whether IRQs are masked on the executing core.
+#[inline(always)]
+pub fn local_irq_is_masked() -> bool {
+    DAIF.is_set(DAIF::I)
+}
+
+/// Mask IRQs on the executing core, returning the previous state for [`local_irq_restore`].
+#[inline(always)]
+pub fn local_irq_mask_save() -> IrqState {
+    let saved = IrqState(DAIF.get());
+    local_irq_mask();
+
+    saved
+}
+
+/// Restore a state saved by [`local_irq_mask_save`].
+#[inline(always)]
+pub fn local_irq_restore(saved: IrqState) {
+    DAIF.set(saved.0);
+}
@@ -0,0 +1,131 @@
+use aarch64_cpu::registers::{ID_AA64MMFR0_EL1, TCR_EL1};
+use tock_registers::{fields::FieldValue, interfaces::Readable};
+
+use crate::board;
+
+use super::{Granule16KiB, Granule4KiB, Granule64KiB};
+
+/// The translation granules of the MMU. The granule is the size of a page and of a translation
+/// table, and sets how many levels of tables translate an address.
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Granule {
+    Size4KiB,
+    Size16KiB,
+    Size64KiB,
+}
+
+/// The granule the kernel is built for, chosen with the `granule_4k` and `granule_16k` features.
+/// The default is 64 KiB.
+#[cfg(not(any(feature = "granule_4k", feature = "granule_16k")))]
+pub const PREFERRED_GRANULE: Granule = Granule::Size64KiB;
+
+/// The granule the kernel is built for, chosen with the `granule_4k` and `granule_16k` features.
+/// The default is 64 KiB.
+#[cfg(feature = "granule_4k")]
+pub const PREFERRED_GRANULE: Granule = Granule::Size4KiB;
+
+/// The granule the kernel is built for, chosen with the `granule_4k` and `granule_16k` features.
+/// The default is 64 KiB.
+#[cfg(feature = "granule_16k")]
+pub const PREFERRED_GRANULE: Granule = Granule::Size16KiB;
+
+#[cfg(all(feature = "granule_4k", feature = "granule_16k"))]
+compile_error!("Only one of the granule_4k and granule_16k features can be enabled");
+
+/// The size of the kernel's address space, as a power of two.
+const ADDRESS_SPACE_SHIFT: usize = board::memory::KernelAddressSpace::SHIFT;
+
+impl Granule {
+    /// The granule's shift, given by log2(size).
+    pub const fn shift(self) -> usize {
+        match self {
+            Granule::Size4KiB => Granule4KiB::SHIFT,
+            Granule::Size16KiB => Granule16KiB::SHIFT,
+            Granule::Size64KiB => Granule64KiB::SHIFT,
+        }
+    }
+
+    /// The size of a page, in bytes.
+    pub const fn size(self) -> usize {
+        1 << self.shift()
+    }
+
+    /// The number of descriptors in a translation table, which fills a page.
+    pub const fn entries_per_table(self) -> usize {
+        self.size() / 8
+    }
+
+    /// The number of address bits translated by each level of tables.
+    const fn bits_per_level(self) -> usize {
+        self.shift() - 3
+    }
+
+    /// The level of the tables that TTBR points to, for the kernel's address space. The last
+    /// level, which holds the page descriptors, is 3.
+    pub const fn start_level(self) -> usize {
+        let levels = (ADDRESS_SPACE_SHIFT - self.shift()).div_ceil(self.bits_per_level());
+
+        // A 48 bit address space with 4 KiB pages takes all four levels, from 0 to 3.
+        assert!(levels <= 4);
+        4 - levels
+    }
+
+    /// The size of the memory that a descriptor at `level` translates.
+    pub const fn block_size(self, level: usize) -> usize {
+        1 << (self.shift() + (3 - level) * self.bits_per_level())
+    }
+
+    /// Whether descriptors at `level` can map a block, rather than point to a table of the next
+    /// level. Levels 1 and 2 can with 4 KiB pages, and only level 2 can with the larger ones.
+    pub const fn has_blocks_at(self, level: usize) -> bool {
+        match self {
+            Granule::Size4KiB => level == 1 || level == 2,
+            Granule::Size16KiB | Granule::Size64KiB => level == 2,
+        }
+    }
+
+    /// The number of descriptors used in the table at the start level, which may be fewer than
+    /// fit into it.
+    pub const fn root_table_entries(self) -> usize {
+        (1 << ADDRESS_SPACE_SHIFT) / self.block_size(self.start_level())
+    }
+
+    /// Whether the executing core implements the granule.
+    pub fn is_supported(self) -> bool {
+        match self {
+            Granule::Size4KiB => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran4::NotSupported),
+            Granule::Size16KiB => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran16::NotSupported),
+            Granule::Size64KiB => !ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::NotSupported),
+        }
+    }
+
+    /// The granule to run with: the preferred one, or else the largest one the executing core
+    /// implements.
+    pub fn select() -> Option<Granule> {
+        if PREFERRED_GRANULE.is_supported() {
+            return Some(PREFERRED_GRANULE);
+        }
+
+        [Granule::Size64KiB, Granule::Size16KiB, Granule::Size4KiB]
+            .into_iter()
+            .find(|x| x.is_supported())
+    }
+
+    /// The granule the executing core translates the kernel's address space with.
+    pub fn current() -> Granule {
+        match TCR_EL1.read_as_enum(TCR_EL1::TG1) {
+            Some(TCR_EL1::TG1::Value::KiB_4) => Granule::Size4KiB,
+            Some(TCR_EL1::TG1::Value::KiB_16) => Granule::Size16KiB,
+            _ => Granule::Size64KiB,
+        }
+    }
+
+    /// The TCR_EL1 fields that select the granule, for both TTBR0 and TTBR1.
+    pub fn tcr_fields(self) -> FieldValue<u64, TCR_EL1::Register> {
+        match self {
+            Granule::Size4KiB => TCR_EL1::TG0::KiB_4 + TCR_EL1::TG1::KiB_4,
+            Granule::Size16KiB => TCR_EL1::TG0::KiB_16 + TCR_EL1::TG1::KiB_16,
+            Granule::Size64KiB => TCR_EL1::TG0::KiB_64 + TCR_EL1::TG1::KiB_64,
+        }
+    }
+}
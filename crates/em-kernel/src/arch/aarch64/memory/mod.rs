@@ -1,31 +1,146 @@
-use core::intrinsics::unlikely;
+use core::{arch::{asm, global_asm}, intrinsics::unlikely, mem, ops::RangeInclusive};
 
-use aarch64_cpu::{registers::{TCR_EL1, MAIR_EL1, SCTLR_EL1, ID_AA64MMFR0_EL1, TTBR0_EL1}, asm::barrier};
-use tock_registers::interfaces::{Readable, ReadWriteable, Writeable};
+use aarch64_cpu::{registers::{TCR_EL1, MAIR_EL1, PAR_EL1, SCTLR_EL1, TTBR0_EL1, TTBR1_EL1}, asm::barrier};
+use tock_registers::{
+    interfaces::{Readable, ReadWriteable, Writeable},
+    register_bitfields,
+    registers::InMemoryRegister,
+};
 
-use crate::{memory::{TranslationGranule, AddressSpace, MemoryManagementUnit, EnableError}, board};
+use crate::{
+    arch,
+    memory::{
+        self, TranslationGranule, AddressSpace, MemoryManagementUnit, EnableError, HwTranslation, Mapping,
+        MappingCounts, MemoryAttributes, PhysicalAddress, Shareability, VirtualAddress,
+    },
+    board,
+    error::Error,
+    sync::IrqSafeMutex,
+    warn,
+};
 
-use self::translation_table::KernelTranslationTable;
+use self::translation_table::{BootTranslationTable, KernelTranslationTable};
 
+pub use self::granule::{Granule, PREFERRED_GRANULE};
+
+mod granule;
 mod translation_table;
 
+global_asm!(
+    include_str!("switch_ttbr1.s"),
+    CONST_TCR_EPD1 = const TCR_EL1::EPD1::DisableTTBR1Walks.value
+);
+
+extern "C" {
+    fn __switch_ttbr1(ttbr1: u64);
+}
+
 struct AArch64MemoryManagementUnit;
 
 pub type Granule512MiB = TranslationGranule<{ 512 * 1024 * 1024 }>;
 pub type Granule64KiB = TranslationGranule<{ 64 * 1024 }>;
+pub type Granule16KiB = TranslationGranule<{ 16 * 1024 }>;
+pub type Granule4KiB = TranslationGranule<{ 4 * 1024 }>;
 
 mod mair {
+    use aarch64_cpu::registers::MAIR_EL1;
+    use tock_registers::interfaces::Readable;
+
+    use crate::memory::MemoryType;
+
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
+    pub const NORMAL_NON_CACHEABLE: u64 = 2;
+    pub const NORMAL_WRITE_THROUGH: u64 = 3;
+    pub const DEVICE_GRE: u64 = 4;
+
+    /// The memory type of the MAIR_EL1 attribute at `index`.
+    pub fn memory_type(index: u64) -> Option<MemoryType> {
+        match index {
+            DEVICE => Some(MemoryType::Device),
+            NORMAL => Some(MemoryType::Normal),
+            NORMAL_NON_CACHEABLE => Some(MemoryType::NormalNonCacheable),
+            NORMAL_WRITE_THROUGH => Some(MemoryType::NormalWriteThrough),
+            DEVICE_GRE => Some(MemoryType::DeviceGre),
+            _ => None,
+        }
+    }
+
+    /// The memory type of an attribute encoded like in MAIR_EL1, as the MMU reports it.
+    pub fn memory_type_of_encoding(attr: u64) -> Option<MemoryType> {
+        let mair = MAIR_EL1.get();
+
+        (DEVICE..=DEVICE_GRE)
+            .find(|index| (mair >> (index * 8)) & 0xff == attr)
+            .and_then(memory_type)
+    }
 }
 
-static mut KERNEL_TABLES: KernelTranslationTable = KernelTranslationTable::new();
+// The fields of PAR_EL1 after a successful address translation, which aarch64-cpu leaves out.
+register_bitfields! {u64,
+    PAR_EL1_SUCCESS [
+        /// The memory attributes, encoded like in MAIR_EL1.
+        ATTR OFFSET(56) NUMBITS(8) [],
+
+        /// Shareability.
+        SH   OFFSET(7) NUMBITS(2) [
+            NonShareable = 0b00,
+            OuterShareable = 0b10,
+            InnerShareable = 0b11
+        ]
+    ]
+}
+
+/// TLB maintenance for changes to live translation tables.
+mod tlb {
+    use core::arch::asm;
+
+    use aarch64_cpu::asm::barrier;
+
+    /// The bits of a TLBI operand that hold VA[55:12]. The bits above are the ASID and the TTL
+    /// level hint, which must stay zero: no hint, and the kernel's global mappings have no ASID.
+    const VA_MASK: usize = (1 << 44) - 1;
+
+    /// Removes the TLB entries of a page on all cores, once its invalidated descriptor is visible
+    /// to their table walks.
+    pub fn invalidate_page(virt_addr: usize) {
+        barrier::dsb(barrier::ISHST);
+        unsafe { asm!("tlbi vale1is, {}", in(reg) (virt_addr >> 12) & VA_MASK, options(nostack)) };
+        barrier::dsb(barrier::ISH);
+    }
+
+    /// Makes the descriptors of a new table visible to the table walks of all cores, before the
+    /// table itself is linked in.
+    pub fn publish_table() {
+        barrier::dsb(barrier::ISHST);
+    }
+
+    /// Makes updated descriptors visible to the table walks of all cores, before any further
+    /// instruction of the executing core uses them.
+    pub fn publish_updates() {
+        barrier::dsb(barrier::ISHST);
+        barrier::isb(barrier::SY);
+    }
+}
+
+// The translation tables the cores enter the kernel with, one per granule. See
+// [`enable_boot_mapping`].
+static BOOT_TABLE_4KIB: BootTranslationTable<{ Granule::Size4KiB.root_table_entries() }> =
+    BootTranslationTable::new(Granule::Size4KiB);
+static BOOT_TABLE_16KIB: BootTranslationTable<{ Granule::Size16KiB.root_table_entries() }> =
+    BootTranslationTable::new(Granule::Size16KiB);
+static BOOT_TABLE_64KIB: BootTranslationTable<{ Granule::Size64KiB.root_table_entries() }> =
+    BootTranslationTable::new(Granule::Size64KiB);
+
+static KERNEL_TABLES: IrqSafeMutex<KernelTranslationTable> =
+    IrqSafeMutex::new(KernelTranslationTable::new());
 static MMU: AArch64MemoryManagementUnit = AArch64MemoryManagementUnit;
 
 impl<const AS_SIZE: usize> AddressSpace<AS_SIZE> {
     /// Checks for architectural restrictions.
     pub const fn arch_check_address_space_size() {
-        // Size must be at least one full 512 MiB table.
+        // Size must be at least one full 512 MiB table, which spans whole last-level tables with
+        // every granule.
         assert!((AS_SIZE % Granule512MiB::SIZE) == 0);
 
         // Check for 48 bit virtual address size as maximum, which is supported by any ARMv8
@@ -44,68 +159,139 @@ impl AArch64MemoryManagementUnit {
             MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc +
 
             // Attribute 0 - Device.
-            MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck,
+            MAIR_EL1::Attr0_Device::nonGathering_nonReordering_EarlyWriteAck +
+
+            // Attribute 2 - Non-cacheable normal DRAM.
+            MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
+            MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
+
+            // Attribute 3 - Write-through normal DRAM.
+            MAIR_EL1::Attr3_Normal_Outer::WriteThrough_NonTransient_ReadAlloc +
+            MAIR_EL1::Attr3_Normal_Inner::WriteThrough_NonTransient_ReadAlloc +
+
+            // Attribute 4 - Device allowing gathering, reordering and early write acknowledgement,
+            // for framebuffers.
+            MAIR_EL1::Attr4_Device::Gathering_Reordering_EarlyWriteAck,
         );
     }
 
     /// Configure various settings of stage 1 of the EL1 translation regime.
-    fn configure_translation_control(&self) {
-        let t0sz = (64 - board::memory::KernelAddressSpace::SHIFT) as u64;
+    ///
+    /// Both halves cover an address space of the kernel's size: TTBR1 the kernel itself at the top,
+    /// TTBR0 the identity mapping of the boot table at the bottom, until the kernel's tables take
+    /// over.
+    fn configure_translation_control(&self, granule: Granule) {
+        let tsz = (64 - board::memory::KernelAddressSpace::SHIFT) as u64;
 
         TCR_EL1.write(
             TCR_EL1::TBI0::Used
                 + TCR_EL1::IPS::Bits_40
-                + TCR_EL1::TG0::KiB_64
+                + granule.tcr_fields()
                 + TCR_EL1::SH0::Inner
                 + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
                 + TCR_EL1::EPD0::EnableTTBR0Walks
-                + TCR_EL1::A1::TTBR0
-                + TCR_EL1::T0SZ.val(t0sz)
-                + TCR_EL1::EPD1::DisableTTBR1Walks,
+                + TCR_EL1::T0SZ.val(tsz)
+                + TCR_EL1::TBI1::Used
+                + TCR_EL1::SH1::Inner
+                + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+                + TCR_EL1::EPD1::EnableTTBR1Walks
+                + TCR_EL1::T1SZ.val(tsz)
+                + TCR_EL1::A1::TTBR0,
         );
     }
 }
 
+/// Turns on the EL1 MMU of the executing core with the boot translation table, so that the
+/// exception return to EL1 can enter the kernel at its linked, virtual address.
+///
+/// The preferred granule is used if the core implements it, and another one otherwise. The
+/// kernel's tables are built for the granule chosen here.
+///
+/// # Safety
+///
+/// - Must be called at EL2, with the MMU of EL1 off.
+/// - Runs from the physical load address, so nothing may use the kernel's linked addresses.
+pub unsafe fn enable_boot_mapping() -> Result<(), EnableError> {
+    let granule = Granule::select().ok_or(EnableError::UnsupportedGranule)?;
+
+    MMU.set_up_mair();
+
+    let boot_table = match granule {
+        Granule::Size4KiB => {
+            BOOT_TABLE_4KIB.link_next_level();
+            BOOT_TABLE_4KIB.phys_base_address()
+        }
+        Granule::Size16KiB => {
+            BOOT_TABLE_16KIB.link_next_level();
+            BOOT_TABLE_16KIB.phys_base_address()
+        }
+        Granule::Size64KiB => {
+            BOOT_TABLE_64KIB.link_next_level();
+            BOOT_TABLE_64KIB.phys_base_address()
+        }
+    };
+    TTBR0_EL1.set_baddr(boot_table);
+    TTBR1_EL1.set_baddr(boot_table);
+
+    MMU.configure_translation_control(granule);
+
+    // The TLBs hold unknown entries out of reset.
+    asm!("tlbi vmalle1", options(nostack));
+    barrier::dsb(barrier::NSH);
+    barrier::isb(barrier::SY);
+
+    // Enable the MMU, and mark data and instructions as cacheable. It takes effect once the core
+    // enters EL1.
+    SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+    barrier::isb(barrier::SY);
+
+    Ok(())
+}
+
 pub fn mmu() -> &'static impl MemoryManagementUnit {
     &MMU
 }
 
 impl MemoryManagementUnit for AArch64MemoryManagementUnit {
     unsafe fn enable(&self) -> Result<(), EnableError> {
-        if unlikely(self.is_enabled()) {
-            return Err(EnableError::AlreadyEnabled)
-        }
+        self.check_can_enable()?;
 
-        // Fail early if the translation granule is not supported
-        if unlikely(!ID_AA64MMFR0_EL1.matches_all(ID_AA64MMFR0_EL1::TGran64::Supported)) {
-            return Err(EnableError::UnsupportedGranule);
+        // The boot code chose the granule.
+        let granule = Granule::current();
+        if granule != PREFERRED_GRANULE {
+            warn!(
+                "{} KiB translation granule not implemented, using {} KiB",
+                PREFERRED_GRANULE.size() / 1024,
+                granule.size() / 1024
+            );
         }
 
-        // Configure memory attributes
-        self.set_up_mair();
+        // The tables are written through the boot table until the switch.
+        let frames_end = match granule {
+            Granule::Size4KiB => BOOT_TABLE_4KIB.ram_end(),
+            Granule::Size16KiB => BOOT_TABLE_16KIB.ram_end(),
+            Granule::Size64KiB => BOOT_TABLE_64KIB.ram_end(),
+        };
 
         // Load translation tables
-        KERNEL_TABLES
-            .populate_tt_entries()
-            .map_err(EnableError::Other)?;
-
-        // Set the Translation Table Base Register (TTBR)
-        TTBR0_EL1.set_baddr(KERNEL_TABLES.base_address().0 as u64);
-
-        self.configure_translation_control();
+        KERNEL_TABLES.lock(|tables| tables.populate_tt_entries(granule, frames_end).map_err(EnableError::Other))?;
 
-        // Enable the MMU!
-        // First, establish an Instruction barrier, to force all previous changes to be seen before processing further instructions
-        barrier::isb(barrier::SY);
+        self.switch_to_kernel_tables();
+        Ok(())
+    }
 
-        // Enable the MMU by setting the bit in the System Control Register.
-        // We're also marking data and instructions as cachable.
-        SCTLR_EL1.modify(SCTLR_EL1::M::Enable + SCTLR_EL1::C::Cacheable + SCTLR_EL1::I::Cacheable);
+    unsafe fn enable_secondary(&self) -> Result<(), EnableError> {
+        self.check_can_enable()?;
 
-        // Another instruction barrier, so that the MMU is active before further instructions are executed
-        barrier::isb(barrier::SY);
+        // The tables were populated by the boot core, so they only need to be loaded, by a core
+        // that runs with the same granule.
+        if KERNEL_TABLES.lock(|tables| tables.granule()) != Granule::current() {
+            return Err(EnableError::UnsupportedGranule);
+        }
 
+        self.switch_to_kernel_tables();
         Ok(())
     }
 
@@ -113,4 +299,126 @@ impl MemoryManagementUnit for AArch64MemoryManagementUnit {
     fn is_enabled(&self) -> bool {
         SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
     }
-}
\ No newline at end of file
+
+    fn page_size(&self) -> usize {
+        Granule::current().size()
+    }
+
+    unsafe fn map(
+        &self,
+        virt: VirtualAddress,
+        phys: PhysicalAddress,
+        len: usize,
+        attributes: &MemoryAttributes,
+    ) -> Result<(), Error> {
+        self.update_tables(|tables| tables.map_pages(virt, phys, len, attributes))
+    }
+
+    unsafe fn unmap(&self, virt: VirtualAddress, len: usize) -> Result<(), Error> {
+        self.update_tables(|tables| tables.unmap_pages(virt, len))
+    }
+
+    unsafe fn protect(&self, virt: VirtualAddress, len: usize, attributes: &MemoryAttributes) -> Result<(), Error> {
+        self.update_tables(|tables| tables.protect_pages(virt, len, attributes))
+    }
+
+    fn count_mappings(&self, virt_range: RangeInclusive<usize>) -> MappingCounts {
+        KERNEL_TABLES.lock(|tables| tables.count_mappings(virt_range))
+    }
+
+    fn translate(&self, virt: VirtualAddress) -> Option<Mapping> {
+        KERNEL_TABLES.lock(|tables| tables.translate(virt.0))
+    }
+
+    fn translate_hw(&self, virt: VirtualAddress) -> Option<HwTranslation> {
+        // Exception handlers may translate addresses as well, which would overwrite the result.
+        let saved = arch::interrupt::local_irq_mask_save();
+        let par = unsafe {
+            asm!("at s1e1r, {}", in(reg) virt.0, options(nostack, preserves_flags));
+            barrier::isb(barrier::SY);
+            PAR_EL1.extract()
+        };
+        arch::interrupt::local_irq_restore(saved);
+
+        if par.is_set(PAR_EL1::F) {
+            return None;
+        }
+
+        let page_offset = virt.0 & ((1 << 12) - 1);
+        let fields = InMemoryRegister::<u64, PAR_EL1_SUCCESS::Register>::new(par.get());
+
+        Some(HwTranslation {
+            phys: PhysicalAddress(((par.read(PAR_EL1::PA) as usize) << 12) | page_offset),
+            memory_type: mair::memory_type_of_encoding(fields.read(PAR_EL1_SUCCESS::ATTR)),
+            shareability: match fields.read_as_enum(PAR_EL1_SUCCESS::SH) {
+                Some(PAR_EL1_SUCCESS::SH::Value::InnerShareable) => Shareability::Inner,
+                Some(PAR_EL1_SUCCESS::SH::Value::OuterShareable) => Shareability::Outer,
+                _ => Shareability::Non,
+            },
+        })
+    }
+
+    fn for_each_mapping(&self, f: &mut dyn FnMut(VirtualAddress, &Mapping)) {
+        KERNEL_TABLES.lock(|tables| tables.for_each_mapping(f))
+    }
+
+    fn find_writable_executable(&self) -> Option<VirtualAddress> {
+        KERNEL_TABLES.lock(|tables| tables.find_writable_executable()).map(VirtualAddress)
+    }
+}
+
+impl AArch64MemoryManagementUnit {
+    /// Applies a change to the live kernel translation tables.
+    fn update_tables(
+        &self,
+        f: impl FnOnce(&mut KernelTranslationTable) -> Result<(), &'static str>,
+    ) -> Result<(), Error> {
+        // Until then, the tables are yet to be populated from the layout.
+        if !self.uses_kernel_tables() {
+            return Err("Kernel translation tables are not in use".into());
+        }
+
+        KERNEL_TABLES.lock(|tables| {
+            let result = f(tables);
+
+            // A change that failed halfway is still published, so the tables and TLBs agree.
+            tlb::publish_updates();
+            result.map_err(Error::from)
+        })
+    }
+
+    /// Whether the executing core switched to the kernel translation tables. The boot code
+    /// enters the kernel with the MMU on already, but only the boot table uses TTBR0.
+    fn uses_kernel_tables(&self) -> bool {
+        TCR_EL1.matches_all(TCR_EL1::EPD0::DisableTTBR0Walks)
+    }
+
+    fn check_can_enable(&self) -> Result<(), EnableError> {
+        if unlikely(self.uses_kernel_tables()) {
+            return Err(EnableError::AlreadyEnabled)
+        }
+
+        Ok(())
+    }
+
+    /// Switches the executing core from the boot translation table to the kernel translation
+    /// tables, and stops translating through TTBR0.
+    unsafe fn switch_to_kernel_tables(&self) {
+        let tables = KERNEL_TABLES.lock(|tables| tables.base_address().0 as u64);
+
+        // The kernel runs from the tables in TTBR1, so it can't replace them from within them. The
+        // switch runs through the identity mapping of the boot table instead.
+        let switch_addr = memory::linear_virt_to_phys(VirtualAddress(__switch_ttbr1 as *const () as usize));
+        let switch_ttbr1: unsafe extern "C" fn(u64) = mem::transmute(switch_addr.0);
+        switch_ttbr1(tables);
+
+        // The identity mapping is no longer needed. TTBR0 is left for the address spaces of user
+        // processes.
+        TCR_EL1.modify(TCR_EL1::EPD0::DisableTTBR0Walks);
+        barrier::isb(barrier::SY);
+
+        asm!("tlbi vmalle1", options(nostack));
+        barrier::dsb(barrier::NSH);
+        barrier::isb(barrier::SY);
+    }
+}
@@ -0,0 +1,35 @@
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+.section .text
+
+//------------------------------------------------------------------------------
+// fn __switch_ttbr1(ttbr1: u64)
+//
+// Replaces the translation table in TTBR1_EL1. TTBR1 walks are disabled until the TLBs no longer
+// hold entries of the old table, so that the old and new translations never meet in the TLBs.
+//
+// The kernel's own mappings are unavailable meanwhile, so this must be called through the identity
+// mapping in TTBR0, and must not touch memory.
+//------------------------------------------------------------------------------
+__switch_ttbr1:
+	// Make the new table visible to the table walks.
+	dsb	ishst
+
+	mrs	x1, TCR_EL1
+	orr	x2, x1, {CONST_TCR_EPD1}
+	msr	TCR_EL1, x2
+	isb
+
+	tlbi	vmalle1
+	dsb	nsh
+	isb
+
+	msr	TTBR1_EL1, x0
+	msr	TCR_EL1, x1
+	isb
+	ret
+
+.size	__switch_ttbr1, . - __switch_ttbr1
+.type	__switch_ttbr1, function
+.global	__switch_ttbr1
@@ -1,16 +1,22 @@
-use core::convert;
+use core::{cell::UnsafeCell, convert, ops::RangeInclusive, ptr};
 
 use tock_registers::{registers::InMemoryRegister, interfaces::{Readable, Writeable}, register_bitfields};
 
-use crate::{board, memory::{PhysicalAddress, MemoryAttributes, MemoryType, MemoryAccess}};
+use crate::{
+    board::{self, memory::{dram, mmio}},
+    memory::{self, frame, KERNEL_VIRT_START, Mapping, MappingCounts, PhysicalAddress, VirtualAddress, MemoryAttributes, MemoryType, MemoryAccess, Shareability},
+};
 
-use super::{Granule512MiB, Granule64KiB, mair};
+use super::{granule::{Granule, PREFERRED_GRANULE}, mair, tlb};
 
 // A table descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-15.
+//
+// Addresses are aligned to the granule, so with 16 KiB and 64 KiB granules the low bits of the
+// address fields are zero.
 register_bitfields! {u64,
     STAGE1_TABLE_DESCRIPTOR [
         /// Physical address of the next descriptor.
-        NEXT_LEVEL_TABLE_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        NEXT_LEVEL_TABLE_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         TYPE  OFFSET(1) NUMBITS(1) [
             Block = 0,
@@ -39,8 +45,8 @@ register_bitfields! {u64,
             True = 1
         ],
 
-        /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
-        OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
+        /// Physical address of the page.
+        OUTPUT_ADDR OFFSET(12) NUMBITS(36) [], // [47:12]
 
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
@@ -50,6 +56,7 @@ register_bitfields! {u64,
 
         /// Shareability field.
         SH       OFFSET(8) NUMBITS(2) [
+            NonShareable = 0b00,
             OuterShareable = 0b10,
             InnerShareable = 0b11
         ],
@@ -77,7 +84,10 @@ register_bitfields! {u64,
     ]
 }
 
-// A table descriptor for 64KiB granules.
+/// The shift of the address fields in descriptors.
+const ADDR_FIELD_SHIFT: usize = 12;
+
+// A table descriptor.
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct TableDescriptor {
@@ -93,9 +103,9 @@ impl TableDescriptor {
     /// Create an instance pointing to the supplied address.
     pub fn from_next_level_table_address(next_level_table_address: PhysicalAddress) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_TABLE_DESCRIPTOR::Register>::new(0);
-        let shifted = next_level_table_address.0 >> Granule64KiB::SHIFT;
+        let shifted = next_level_table_address.0 >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.val(shifted as u64)
+            STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR.val(shifted as u64)
                 + STAGE1_TABLE_DESCRIPTOR::TYPE::Table
                 + STAGE1_TABLE_DESCRIPTOR::VALID::True,
         );
@@ -104,7 +114,56 @@ impl TableDescriptor {
     }
 }
 
-/// A page descriptor for 64KiB granules.
+/// A block descriptor, which maps the memory of a whole table of the next level at once.
+///
+/// Its attribute fields are the same as those of a page descriptor.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct BlockDescriptor {
+    value: u64,
+}
+
+impl BlockDescriptor {
+    /// Create a new zeroed instance.
+    pub const fn new_zeroed() -> Self {
+        Self { value: 0 }
+    }
+
+    /// Create an instance mapping normal memory that EL1 can write and execute.
+    ///
+    /// Built from the raw field values, so that it can be used in constants.
+    const fn normal_rwx(output_address: PhysicalAddress, block_size: usize) -> Self {
+        assert!(output_address.0 % block_size == 0);
+
+        Self {
+            value: output_address.0 as u64
+                | STAGE1_PAGE_DESCRIPTOR::UXN::True.value
+                | STAGE1_PAGE_DESCRIPTOR::AF::True.value
+                | STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable.value
+                | STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1.value
+                | STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL).value
+                | STAGE1_TABLE_DESCRIPTOR::TYPE::Block.value
+                | STAGE1_TABLE_DESCRIPTOR::VALID::True.value,
+        }
+    }
+
+    /// Create an instance mapping a block at the supplied address.
+    pub fn from_output_address(output_address: PhysicalAddress, attributes: &MemoryAttributes) -> Self {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
+        let shifted = output_address.0 >> ADDR_FIELD_SHIFT;
+        val.write(
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted as u64)
+                + STAGE1_PAGE_DESCRIPTOR::AF::True
+                + STAGE1_PAGE_DESCRIPTOR::VALID::True
+                + attributes.into(),
+        );
+
+        // The type is left at 0, which marks a block in its level.
+        Self { value: val.get() }
+    }
+}
+
+/// A page descriptor.
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct PageDescriptor {
@@ -120,9 +179,9 @@ impl PageDescriptor {
     /// Create an instance
     pub fn from_output_address(output_address: PhysicalAddress, attributes: &MemoryAttributes) -> Self {
         let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(0);
-        let shifted = output_address.0 >> Granule64KiB::SHIFT;
+        let shifted = output_address.0 >> ADDR_FIELD_SHIFT;
         val.write(
-            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted as u64)
+            STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.val(shifted as u64)
                 + STAGE1_PAGE_DESCRIPTOR::AF::True
                 + STAGE1_PAGE_DESCRIPTOR::TYPE::Page
                 + STAGE1_PAGE_DESCRIPTOR::VALID::True
@@ -131,17 +190,18 @@ impl PageDescriptor {
 
         Self { value: val.get() }
     }
-}
 
-trait StartAddress {
-    fn physical_start_address(&self) -> PhysicalAddress;
-}
+    fn is_valid(&self) -> bool {
+        InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value)
+            .is_set(STAGE1_PAGE_DESCRIPTOR::VALID)
+    }
 
-const NUM_LVL2_TABLES: usize = board::memory::KernelAddressSpace::SIZE >> Granule512MiB::SHIFT;
+    /// The physical address of the page.
+    fn output_address(&self) -> PhysicalAddress {
+        let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(self.value);
+        let shifted = val.read(STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR) as usize;
 
-impl<T, const N:usize> StartAddress for [T; N] {
-    fn physical_start_address(&self) -> PhysicalAddress {
-        PhysicalAddress(self as *const T as usize)
+        PhysicalAddress(shifted << ADDR_FIELD_SHIFT)
     }
 }
 
@@ -149,80 +209,759 @@ impl<T, const N:usize> StartAddress for [T; N] {
 impl convert::From<&MemoryAttributes> for tock_registers::fields::FieldValue<u64, STAGE1_PAGE_DESCRIPTOR::Register> {
     fn from(attributes: &MemoryAttributes) -> Self {
         // Memory attributes.
-        let mut desc = match attributes.memory_type {
-            MemoryType::Normal => {
-                STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable
-                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::NORMAL)
-            }
-            MemoryType::Device => {
-                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
-                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
-            }
+        let mut desc = STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(match attributes.memory_type {
+            MemoryType::Normal => mair::NORMAL,
+            MemoryType::NormalNonCacheable => mair::NORMAL_NON_CACHEABLE,
+            MemoryType::NormalWriteThrough => mair::NORMAL_WRITE_THROUGH,
+            MemoryType::Device => mair::DEVICE,
+            MemoryType::DeviceGre => mair::DEVICE_GRE,
+        });
+
+        // Shareability.
+        desc += match attributes.shareability {
+            Shareability::Non => STAGE1_PAGE_DESCRIPTOR::SH::NonShareable,
+            Shareability::Inner => STAGE1_PAGE_DESCRIPTOR::SH::InnerShareable,
+            Shareability::Outer => STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable,
         };
 
         // Access Permissions.
-        desc += match attributes.access {
-            MemoryAccess::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
-            MemoryAccess::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+        desc += match (&attributes.access, attributes.user_accessible) {
+            (MemoryAccess::ReadOnly, false) => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
+            (MemoryAccess::ReadWrite, false) => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+            (MemoryAccess::ReadOnly, true) => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1_EL0,
+            (MemoryAccess::ReadWrite, true) => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1_EL0,
         };
 
-        // The execute-never attribute is mapped to PXN in AArch64.
-        desc += if attributes.executable {
-            STAGE1_PAGE_DESCRIPTOR::PXN::False
-        } else {
+        // The kernel never executes memory that user space can access, and user space never
+        // executes the kernel's.
+        desc += if attributes.user_accessible {
             STAGE1_PAGE_DESCRIPTOR::PXN::True
+                + if attributes.executable {
+                    STAGE1_PAGE_DESCRIPTOR::UXN::False
+                } else {
+                    STAGE1_PAGE_DESCRIPTOR::UXN::True
+                }
+        } else {
+            STAGE1_PAGE_DESCRIPTOR::UXN::True
+                + if attributes.executable {
+                    STAGE1_PAGE_DESCRIPTOR::PXN::False
+                } else {
+                    STAGE1_PAGE_DESCRIPTOR::PXN::True
+                }
         };
 
-        // Always set unprivileged execute-never as long as userspace is not implemented yet.
-        desc += STAGE1_PAGE_DESCRIPTOR::UXN::True;
-
         desc
     }
 }
 
-/// Represents all the translation tables for the kernel.
+/// Whether a descriptor above level 3 points to a table. At level 3, the same type marks a page.
+fn is_table(desc: u64) -> bool {
+    desc & 0b11 == STAGE1_TABLE_DESCRIPTOR::TYPE::Table.value | STAGE1_TABLE_DESCRIPTOR::VALID::True.value
+}
+
+fn is_valid(desc: u64) -> bool {
+    desc & STAGE1_TABLE_DESCRIPTOR::VALID::True.value != 0
+}
+
+/// The address in a table, block or page descriptor.
+fn output_address(desc: u64) -> PhysicalAddress {
+    let shifted = STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.read(desc) as usize;
+
+    PhysicalAddress(shifted << ADDR_FIELD_SHIFT)
+}
+
+/// Whether a block or page descriptor allows writing and executing the memory, at any exception
+/// level.
+fn is_writable_executable(desc: u64) -> bool {
+    let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(desc);
+
+    let writable = matches!(
+        val.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP),
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1 | STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1_EL0)
+    );
+    let executable = !val.is_set(STAGE1_PAGE_DESCRIPTOR::PXN) || !val.is_set(STAGE1_PAGE_DESCRIPTOR::UXN);
+
+    writable && executable
+}
+
+/// The attributes of a block or page descriptor. The inverse of building the descriptor from
+/// [`MemoryAttributes`].
+fn attributes(desc: u64) -> MemoryAttributes {
+    let val = InMemoryRegister::<u64, STAGE1_PAGE_DESCRIPTOR::Register>::new(desc);
+
+    let memory_type = mair::memory_type(val.read(STAGE1_PAGE_DESCRIPTOR::AttrIndx)).unwrap_or(MemoryType::Normal);
+
+    let shareability = match val.read_as_enum(STAGE1_PAGE_DESCRIPTOR::SH) {
+        Some(STAGE1_PAGE_DESCRIPTOR::SH::Value::InnerShareable) => Shareability::Inner,
+        Some(STAGE1_PAGE_DESCRIPTOR::SH::Value::OuterShareable) => Shareability::Outer,
+        _ => Shareability::Non,
+    };
+
+    let (access, user_accessible) = match val.read_as_enum(STAGE1_PAGE_DESCRIPTOR::AP) {
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1) => (MemoryAccess::ReadWrite, false),
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RW_EL1_EL0) => (MemoryAccess::ReadWrite, true),
+        Some(STAGE1_PAGE_DESCRIPTOR::AP::Value::RO_EL1_EL0) => (MemoryAccess::ReadOnly, true),
+        _ => (MemoryAccess::ReadOnly, false),
+    };
+
+    let executable = if user_accessible {
+        !val.is_set(STAGE1_PAGE_DESCRIPTOR::UXN)
+    } else {
+        !val.is_set(STAGE1_PAGE_DESCRIPTOR::PXN)
+    };
+
+    MemoryAttributes {
+        memory_type,
+        access,
+        executable,
+        user_accessible,
+        shareability,
+    }
+}
+
+/// The physical address of a table, for linking it into the tree.
+fn table_address(table: *mut u64) -> PhysicalAddress {
+    memory::linear_virt_to_phys(VirtualAddress(table as usize))
+}
+
+/// Checks that a range of virtual addresses covers whole pages.
+fn check_page_range(virt_addr: usize, len: usize, page_size: usize) -> Result<(), &'static str> {
+    if virt_addr % page_size != 0 || len % page_size != 0 {
+        return Err("Range is not page aligned");
+    }
+
+    virt_addr.checked_add(len).ok_or("Range overflows the address space")?;
+    Ok(())
+}
+
+/// The physical RAM.
+const RAM: RangeInclusive<usize> = dram::START..=dram::END_EXCLUSIVE - 1;
+
+// Normal memory may be accessed speculatively, which devices must never be. Blocks of the boot
+// table that hold RAM can only leave out the devices if they don't share any memory.
+const _: () = assert!(!overlaps(&RAM, &(mmio::START..=mmio::END_INCLUSIVE)));
+
+/// Whether two ranges share any address.
+const fn overlaps(a: &RangeInclusive<usize>, b: &RangeInclusive<usize>) -> bool {
+    *a.start() <= *b.end() && *b.start() <= *a.end()
+}
+
+/// Whether the boot table maps the `size` bytes of physical memory at `start`: they must hold RAM,
+/// and no devices.
+const fn is_boot_mapped(start: usize, size: usize) -> bool {
+    let block = start..=start + (size - 1);
+
+    overlaps(&block, &RAM) && !overlaps(&block, &(mmio::START..=mmio::END_INCLUSIVE))
+}
+
+/// The number of descriptors in the table of the level below the root of a boot table, which only
+/// the 4 KiB granule uses.
+const BOOT_NEXT_LEVEL_ENTRIES: usize = Granule::Size4KiB.entries_per_table();
+
+/// The translation table the cores boot with, until they switch to the kernel translation tables.
+///
+/// A table at the granule's start level, whose blocks map the RAM as normal memory that EL1 can
+/// write and execute. Blocks that would map devices as well are left out. If that leaves out the
+/// start of the RAM, where the kernel is loaded, the smaller blocks of the next level map it
+/// instead, which only the 4 KiB granule has.
+///
+/// It serves both TTBR0, as an identity mapping for the code running at physical addresses, and
+/// TTBR1, as the start of the kernel's linear mapping.
 #[repr(C)]
-#[repr(align(65536))]
-pub struct FixedSizeTranslationTable<const NUM_TABLES: usize> {
-    /// The level 3 tables (page descriptors).
-    lvl3: [[PageDescriptor; 8192]; NUM_TABLES],
+#[repr(align(4096))]
+pub struct BootTranslationTable<const ENTRIES: usize> {
+    /// The blocks of the next level, which replace the root block at `next_index`.
+    next: [BlockDescriptor; BOOT_NEXT_LEVEL_ENTRIES],
+
+    root: UnsafeCell<[BlockDescriptor; ENTRIES]>,
+
+    /// The root descriptor that points to `next` once it is linked in.
+    next_index: Option<usize>,
 
-    /// The single level 2 table (table descriptors).
-    lvl2: [TableDescriptor; NUM_TABLES],
+    /// The end of the RAM that is mapped from its start on.
+    ram_end: usize,
 }
 
-pub type KernelTranslationTable = FixedSizeTranslationTable<NUM_LVL2_TABLES>;
+// The root table only changes once the cores link the next level in, which they do alike.
+unsafe impl<const ENTRIES: usize> Sync for BootTranslationTable<ENTRIES> {}
+
+impl<const ENTRIES: usize> BootTranslationTable<ENTRIES> {
+    /// Create an instance for `granule`, which must have `ENTRIES` descriptors at its start level.
+    pub const fn new(granule: Granule) -> Self {
+        let level = granule.start_level();
+        let block_size = granule.block_size(level);
+
+        // Level 0 can't hold blocks, and a table larger than a page would need a larger alignment.
+        assert!(level > 0 && ENTRIES == granule.root_table_entries() && ENTRIES * 8 <= 4096);
+
+        let mut root = [BlockDescriptor::new_zeroed(); ENTRIES];
+        let mut next = [BlockDescriptor::new_zeroed(); BOOT_NEXT_LEVEL_ENTRIES];
+        let mut next_index = None;
+        let next_size = granule.block_size(level + 1);
+
+        let mut i = 0;
+        while i < ENTRIES {
+            let start = i * block_size;
+            let holds_ram_start = overlaps(&(start..=start + (block_size - 1)), &(dram::START..=dram::START));
+
+            if is_boot_mapped(start, block_size) {
+                root[i] = BlockDescriptor::normal_rwx(PhysicalAddress(start), block_size);
+            } else if holds_ram_start && granule.has_blocks_at(level + 1) {
+                assert!(granule.entries_per_table() == BOOT_NEXT_LEVEL_ENTRIES);
+
+                let mut j = 0;
+                while j < BOOT_NEXT_LEVEL_ENTRIES {
+                    let next_start = start + j * next_size;
+                    if is_boot_mapped(next_start, next_size) {
+                        next[j] = BlockDescriptor::normal_rwx(PhysicalAddress(next_start), next_size);
+                    }
+                    j += 1;
+                }
+
+                next_index = Some(i);
+            }
 
-impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
-    /// Create an instance.
-    pub const fn new() -> Self {
-        // Can't have a zero-sized address space.
-        assert!(NUM_TABLES > 0);
+            i += 1;
+        }
+
+        // Besides the kernel image, the kernel tables are allocated from the mapped RAM before the
+        // switch.
+        let mut ram_end = dram::START;
+        while ram_end < dram::END_EXCLUSIVE {
+            let i = ram_end / block_size;
+            let (desc, size) = match next_index {
+                Some(x) if x == i => (next[(ram_end % block_size) / next_size], next_size),
+                _ => (root[i], block_size),
+            };
+
+            if desc.value & STAGE1_TABLE_DESCRIPTOR::VALID::True.value == 0 {
+                break;
+            }
+            ram_end = (ram_end - ram_end % size) + size;
+        }
+
+        assert!(ram_end > dram::START, "Boot table doesn't map the start of the RAM");
 
         Self {
-            lvl3: [[PageDescriptor::new_zeroed(); 8192]; NUM_TABLES],
-            lvl2: [TableDescriptor::new_zeroed(); NUM_TABLES],
+            next,
+            root: UnsafeCell::new(root),
+            next_index,
+            ram_end: if ram_end < dram::END_EXCLUSIVE { ram_end } else { dram::END_EXCLUSIVE },
         }
     }
 
-    /// Iterates over all static translation table entries and fills them at once.
+    /// Links the table of the next level into the root table, which takes its address and can't
+    /// be done at compile time.
     ///
     /// # Safety
     ///
-    /// - Modifies a `static mut`. Ensure it only happens from here.
-    pub unsafe fn populate_tt_entries(&mut self) -> Result<(), &'static str> {
-        for (l2_nr, l2_entry) in self.lvl2.iter_mut().enumerate() {
-            *l2_entry =
-                TableDescriptor::from_next_level_table_address(self.lvl3[l2_nr].physical_start_address());
+    /// - Same as [`BootTranslationTable::phys_base_address`], before the MMU uses the table.
+    #[inline(always)]
+    pub unsafe fn link_next_level(&self) {
+        if let Some(index) = self.next_index {
+            let next = PhysicalAddress(self.next.as_ptr() as usize);
+            let entry = (self.root.get() as *mut u64).add(index);
+
+            entry.write_volatile(TableDescriptor::from_next_level_table_address(next).value);
+        }
+    }
+
+    /// The end of the RAM the table maps from its start on, where the kernel image is loaded.
+    pub fn ram_end(&self) -> PhysicalAddress {
+        PhysicalAddress(self.ram_end)
+    }
+
+    /// The table's address, for programming the MMU.
+    ///
+    /// Only valid while running from physical addresses, where the PC-relative address of the
+    /// table is its physical address.
+    #[inline(always)]
+    pub fn phys_base_address(&self) -> u64 {
+        self.root.get() as u64
+    }
+}
+
+/// Represents all the translation tables for the kernel.
+///
+/// The tables are built from the board's layout, with the largest descriptors it allows: a range
+/// that translates alike across a whole block is mapped by a block descriptor, or left invalid if
+/// it is unmapped. Tables below are only created where needed, in memory taken from the frame
+/// allocator. Mappings added at runtime create the tables they need, and split blocks they land
+/// in.
+pub struct FixedSizeTranslationTable {
+    /// The granule of the tables. Replaced by the one the MMU runs with when they are populated.
+    granule: Granule,
+
+    /// The table at the granule's start level, or null until the tables are populated.
+    root: *mut u64,
+
+    /// Memory for further tables, carved from the frame taken last from the frame allocator.
+    spare: *mut u64,
+
+    /// The number of tables that fit into `spare`.
+    spare_tables: usize,
+
+    /// The end of the physical memory that tables are allocated from. While the tables are
+    /// populated, they are written through the boot table, which only maps part of the RAM.
+    frames_end: usize,
+}
+
+// The tables are only accessed with the kernel's lock around them held.
+unsafe impl Send for FixedSizeTranslationTable {}
+
+pub type KernelTranslationTable = FixedSizeTranslationTable;
+
+impl FixedSizeTranslationTable {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            granule: PREFERRED_GRANULE,
+            root: ptr::null_mut(),
+            spare: ptr::null_mut(),
+            spare_tables: 0,
+            frames_end: usize::MAX,
+        }
+    }
+
+    /// The granule of the tables.
+    pub fn granule(&self) -> Granule {
+        self.granule
+    }
+
+    /// Allocates an empty table.
+    fn alloc_table(&mut self) -> Result<*mut u64, &'static str> {
+        let entries = self.granule.entries_per_table();
+
+        if self.spare_tables == 0 {
+            let frame = frame::alloc_below(PhysicalAddress(self.frames_end))
+                .map_err(|_| "Out of memory for translation tables")?;
+            self.spare = memory::phys_to_virt(frame).0 as *mut u64;
+            self.spare_tables = frame::FRAME_SIZE / self.granule.size();
+        }
+
+        let table = self.spare;
+        unsafe {
+            self.spare = table.add(entries);
+            ptr::write_bytes(table, 0, entries);
+        }
+        self.spare_tables -= 1;
+
+        Ok(table)
+    }
+
+    /// The offset of `virt_addr` into the kernel's address space.
+    fn offset(&self, virt_addr: usize) -> Result<usize, &'static str> {
+        if self.root.is_null() {
+            return Err("Translation tables not populated");
+        }
+
+        virt_addr.checked_sub(KERNEL_VIRT_START).ok_or("Virtual address out of range")
+    }
+
+    /// The index of the descriptor translating `offset` in its table at `level`.
+    fn index(&self, offset: usize, level: usize) -> usize {
+        (offset / self.granule.block_size(level)) % self.granule.entries_per_table()
+    }
+
+    /// Allocates the tables for `granule`, and fills them from the board's layout at once. The
+    /// tables are taken from the physical memory below `frames_end`, which must be mapped.
+    pub fn populate_tt_entries(&mut self, granule: Granule, frames_end: PhysicalAddress) -> Result<(), &'static str> {
+        if !self.root.is_null() {
+            return Err("Translation tables already populated");
+        }
+
+        self.granule = granule;
+        self.frames_end = frames_end.0;
+        self.root = self.alloc_table()?;
+
+        let result =
+            self.populate_table(self.root, granule.start_level(), KERNEL_VIRT_START, granule.root_table_entries());
+
+        // Tables added later are written through the tables populated here, which map all RAM.
+        self.frames_end = usize::MAX;
+        result
+    }
+
+    /// Fills the first `entries` descriptors of `table` at `level`, which translates the addresses
+    /// from `virt_start` on.
+    fn populate_table(
+        &mut self,
+        table: *mut u64,
+        level: usize,
+        virt_start: usize,
+        entries: usize,
+    ) -> Result<(), &'static str> {
+        let layout = board::memory::virtual_memory_layout();
+        let block_size = self.granule.block_size(level);
+
+        for entry in 0..entries {
+            let virt_addr = virt_start + entry * block_size;
+            let uniform = level < 3 && layout.is_uniform(virt_addr, block_size);
+
+            let desc = match layout.virt_addr_properties(virt_addr)? {
+                None if level == 3 => PageDescriptor::new_zeroed().value,
+
+                // Tables for unmapped blocks are created once mappings are added.
+                None if uniform => TableDescriptor::new_zeroed().value,
+
+                Some((phys_output_addr, attribute_fields)) if level == 3 => {
+                    PageDescriptor::from_output_address(phys_output_addr, &attribute_fields).value
+                }
+
+                Some((phys_output_addr, attribute_fields))
+                    if uniform
+                        && self.granule.has_blocks_at(level)
+                        && phys_output_addr.0 % block_size == 0 =>
+                {
+                    BlockDescriptor::from_output_address(phys_output_addr, &attribute_fields).value
+                }
+
+                _ => {
+                    let next = self.alloc_table()?;
+                    self.populate_table(next, level + 1, virt_addr, self.granule.entries_per_table())?;
+
+                    TableDescriptor::from_next_level_table_address(table_address(next)).value
+                }
+            };
+
+            unsafe { table.add(entry).write(desc) };
+        }
+
+        Ok(())
+    }
+
+    /// The descriptor translating `virt_addr`, and its level: a page, a block, or the invalid
+    /// descriptor the walk ended at.
+    fn lookup(&self, virt_addr: usize) -> Result<(usize, u64), &'static str> {
+        let offset = self.offset(virt_addr)?;
+        let mut table = self.root;
+        let mut level = self.granule.start_level();
+
+        loop {
+            // The table walkers may update the tables concurrently, so every access must happen.
+            let desc = unsafe { table.add(self.index(offset, level)).read_volatile() };
+            if level == 3 || !is_table(desc) {
+                return Ok((level, desc));
+            }
+
+            table = memory::phys_to_virt(output_address(desc)).0 as *mut u64;
+            level += 1;
+        }
+    }
+
+    /// The first address mapped both writable and executable, at EL1 or EL0, if any.
+    pub fn find_writable_executable(&self) -> Option<usize> {
+        if self.root.is_null() {
+            return None;
+        }
+
+        self.find_writable_executable_in(
+            self.root,
+            self.granule.start_level(),
+            KERNEL_VIRT_START,
+            self.granule.root_table_entries(),
+        )
+    }
+
+    /// Searches the first `entries` descriptors of `table` at `level`, and the tables below.
+    fn find_writable_executable_in(
+        &self,
+        table: *mut u64,
+        level: usize,
+        virt_start: usize,
+        entries: usize,
+    ) -> Option<usize> {
+        let block_size = self.granule.block_size(level);
+
+        (0..entries).find_map(|entry| {
+            let virt_addr = virt_start + entry * block_size;
+            let desc = unsafe { table.add(entry).read_volatile() };
+
+            if level < 3 && is_table(desc) {
+                let next = memory::phys_to_virt(output_address(desc)).0 as *mut u64;
+                self.find_writable_executable_in(next, level + 1, virt_addr, self.granule.entries_per_table())
+            } else if is_valid(desc) && is_writable_executable(desc) {
+                Some(virt_addr)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// How `virt_addr` is translated, if it is mapped.
+    pub fn translate(&self, virt_addr: usize) -> Option<Mapping> {
+        let (level, desc) = self.lookup(virt_addr).ok()?;
+        if !is_valid(desc) {
+            return None;
+        }
+
+        let size = self.granule.block_size(level);
+
+        Some(Mapping {
+            phys: PhysicalAddress(output_address(desc).0 + (virt_addr & (size - 1))),
+            attributes: attributes(desc),
+            size,
+        })
+    }
+
+    /// Calls `f` for every page and block, in address order, with its start address.
+    pub fn for_each_mapping(&self, f: &mut dyn FnMut(VirtualAddress, &Mapping)) {
+        if self.root.is_null() {
+            return;
+        }
+
+        self.for_each_mapping_in(
+            self.root,
+            self.granule.start_level(),
+            KERNEL_VIRT_START,
+            self.granule.root_table_entries(),
+            f,
+        );
+    }
+
+    /// Visits the first `entries` descriptors of `table` at `level`, and the tables below.
+    fn for_each_mapping_in(
+        &self,
+        table: *mut u64,
+        level: usize,
+        virt_start: usize,
+        entries: usize,
+        f: &mut dyn FnMut(VirtualAddress, &Mapping),
+    ) {
+        let block_size = self.granule.block_size(level);
+
+        for entry in 0..entries {
+            let virt_addr = virt_start + entry * block_size;
+            let desc = unsafe { table.add(entry).read_volatile() };
+
+            if level < 3 && is_table(desc) {
+                let next = memory::phys_to_virt(output_address(desc)).0 as *mut u64;
+                self.for_each_mapping_in(next, level + 1, virt_addr, self.granule.entries_per_table(), f);
+            } else if is_valid(desc) {
+                let mapping = Mapping {
+                    phys: output_address(desc),
+                    attributes: attributes(desc),
+                    size: block_size,
+                };
+
+                f(VirtualAddress(virt_addr), &mapping);
+            }
+        }
+    }
+
+    /// Counts the blocks and pages that translate `virt_range`, including those that only partly
+    /// overlap it.
+    pub fn count_mappings(&self, virt_range: RangeInclusive<usize>) -> MappingCounts {
+        let mut counts = MappingCounts { blocks: 0, pages: 0 };
+        let mut virt_addr = *virt_range.start();
+
+        while virt_addr <= *virt_range.end() {
+            let Ok((level, desc)) = self.lookup(virt_addr) else {
+                break;
+            };
+
+            match (is_valid(desc), level) {
+                (false, _) => {}
+                (true, 3) => counts.pages += 1,
+                (true, _) => counts.blocks += 1,
+            }
+
+            let size = self.granule.block_size(level);
+            match (virt_addr & !(size - 1)).checked_add(size) {
+                Some(next) => virt_addr = next,
+                None => break,
+            }
+        }
+
+        counts
+    }
+
+    /// Maps `len` bytes at `virt` to `phys`, replacing any existing mapping.
+    pub fn map_pages(
+        &mut self,
+        virt: VirtualAddress,
+        phys: PhysicalAddress,
+        len: usize,
+        attributes: &MemoryAttributes,
+    ) -> Result<(), &'static str> {
+        let page_size = self.granule.size();
+        check_page_range(virt.0, len, page_size)?;
+        if phys.0 % page_size != 0 {
+            return Err("Physical address is not page aligned");
+        }
+
+        self.prepare_pages(virt.0, len, false)?;
+
+        for offset in (0..len).step_by(page_size) {
+            let desc = PageDescriptor::from_output_address(PhysicalAddress(phys.0 + offset), attributes);
+            self.set_page_descriptor(virt.0 + offset, desc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Removes the mapping of `len` bytes at `virt`. Pages that are not mapped are skipped.
+    pub fn unmap_pages(&mut self, virt: VirtualAddress, len: usize) -> Result<(), &'static str> {
+        let page_size = self.granule.size();
+        check_page_range(virt.0, len, page_size)?;
+
+        self.prepare_pages(virt.0, len, true)?;
+
+        for offset in (0..len).step_by(page_size) {
+            // No tables are created just to hold invalid descriptors.
+            if is_valid(self.lookup(virt.0 + offset)?.1) {
+                self.set_page_descriptor(virt.0 + offset, PageDescriptor::new_zeroed())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Changes the attributes of `len` bytes at `virt`, keeping their physical addresses.
+    pub fn protect_pages(
+        &mut self,
+        virt: VirtualAddress,
+        len: usize,
+        attributes: &MemoryAttributes,
+    ) -> Result<(), &'static str> {
+        let page_size = self.granule.size();
+        check_page_range(virt.0, len, page_size)?;
+
+        // Check the whole range first, so that it is either changed entirely or not at all.
+        for offset in (0..len).step_by(page_size) {
+            if !is_valid(self.lookup(virt.0 + offset)?.1) {
+                return Err("Page is not mapped");
+            }
+        }
+
+        self.prepare_pages(virt.0, len, true)?;
+
+        for offset in (0..len).step_by(page_size) {
+            let phys = self.page_descriptor_mut(virt.0 + offset)?.output_address();
+            let desc = PageDescriptor::from_output_address(phys, attributes);
+            self.set_page_descriptor(virt.0 + offset, desc)?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates the tables holding the page descriptors of `len` bytes at `virt_addr`, splitting
+    /// the blocks on the way, and skipping the pages that are not mapped if `mapped_only` is set.
+    ///
+    /// The translation stays the same, and afterwards nothing is left to fail when the page
+    /// descriptors are replaced. So a range is either changed entirely or not at all.
+    fn prepare_pages(&mut self, virt_addr: usize, len: usize, mapped_only: bool) -> Result<(), &'static str> {
+        if len == 0 {
+            return Ok(());
+        }
 
-            for (l3_nr, l3_entry) in self.lvl3[l2_nr].iter_mut().enumerate() {
-                let virt_addr = (l2_nr << Granule512MiB::SHIFT) + (l3_nr << Granule64KiB::SHIFT);
+        // Fail before any table is changed if the range leaves the address space.
+        self.lookup(virt_addr)?;
+        self.lookup(virt_addr + len - 1)?;
 
-                let (phys_output_addr, attribute_fields) =
-                    board::memory::virtual_memory_layout().virt_addr_properties(virt_addr)?;
+        for page in (virt_addr..virt_addr + len).step_by(self.granule.size()) {
+            if !mapped_only || is_valid(self.lookup(page)?.1) {
+                self.page_descriptor_mut(page)?;
+            }
+        }
 
-                *l3_entry = PageDescriptor::from_output_address(phys_output_addr, &attribute_fields);
+        Ok(())
+    }
+
+    /// The page descriptor translating `virt_addr`.
+    ///
+    /// Missing tables on the way are created. A block on the way is replaced by a table that
+    /// translates alike, so that the page can change on its own. The whole block is unmapped for a
+    /// moment meanwhile.
+    fn page_descriptor_mut(&mut self, virt_addr: usize) -> Result<&mut PageDescriptor, &'static str> {
+        let offset = self.offset(virt_addr)?;
+        let mut table = self.root;
+        let mut level = self.granule.start_level();
+
+        while level < 3 {
+            let entry = unsafe { table.add(self.index(offset, level)) };
+            let desc = unsafe { entry.read_volatile() };
+
+            table = if is_table(desc) {
+                memory::phys_to_virt(output_address(desc)).0 as *mut u64
+            } else {
+                // While the block is broken, nothing it maps can be accessed, including the table
+                // that holds it.
+                let block_size = self.granule.block_size(level);
+                if is_valid(desc) && (entry as usize) & !(block_size - 1) == virt_addr & !(block_size - 1) {
+                    return Err("Block holds its own translation table");
+                }
+
+                let next = self.alloc_table()?;
+                if is_valid(desc) {
+                    self.split_block(desc, next, level + 1);
+                }
+
+                // The table must be complete before a table walk can find it. A block is broken
+                // before the table replaces it, like a page in `set_page_descriptor`.
+                tlb::publish_table();
+                unsafe {
+                    if is_valid(desc) {
+                        entry.write_volatile(BlockDescriptor::new_zeroed().value);
+                        tlb::invalidate_page(virt_addr);
+                    }
+
+                    let table_desc = TableDescriptor::from_next_level_table_address(table_address(next));
+                    entry.write_volatile(table_desc.value);
+                }
+
+                next
+            };
+
+            level += 1;
+        }
+
+        Ok(unsafe { &mut *(table.add(self.index(offset, 3)) as *mut PageDescriptor) })
+    }
+
+    /// Fills `table` at `level` with the descriptors that translate like `block` did.
+    fn split_block(&self, block: u64, table: *mut u64, level: usize) {
+        let size = self.granule.block_size(level);
+        let start = output_address(block).0;
+
+        // What remains besides the address and the type are the attributes.
+        let address_mask = STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.mask << STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR.shift;
+        let attributes = block & !address_mask & !STAGE1_PAGE_DESCRIPTOR::TYPE::SET.value;
+        let kind = if level == 3 {
+            STAGE1_PAGE_DESCRIPTOR::TYPE::Page.value
+        } else {
+            assert!(self.granule.has_blocks_at(level));
+            STAGE1_TABLE_DESCRIPTOR::TYPE::Block.value
+        };
+
+        for entry in 0..self.granule.entries_per_table() {
+            let desc = attributes | kind | (start + entry * size) as u64;
+            unsafe { table.add(entry).write(desc) };
+        }
+    }
+
+    /// Replaces a live page descriptor.
+    ///
+    /// A valid descriptor is not allowed to change in place, as other cores may hold TLB entries
+    /// for it. It is broken first: invalidated, and the TLB entries removed on all cores, before
+    /// the new descriptor is made. The caller publishes the new descriptors with
+    /// `tlb::publish_updates` once done.
+    fn set_page_descriptor(&mut self, virt_addr: usize, new: PageDescriptor) -> Result<(), &'static str> {
+        let entry: *mut PageDescriptor = self.page_descriptor_mut(virt_addr)?;
+
+        // The table walkers read the descriptors concurrently, so every access must happen.
+        unsafe {
+            let old = entry.read_volatile();
+            if old.value == new.value {
+                return Ok(());
             }
+
+            if old.is_valid() {
+                entry.write_volatile(PageDescriptor::new_zeroed());
+                tlb::invalidate_page(virt_addr);
+            }
+
+            entry.write_volatile(new);
         }
 
         Ok(())
@@ -230,6 +969,6 @@ impl<const NUM_TABLES: usize> FixedSizeTranslationTable<NUM_TABLES> {
 
     /// The translation table's base address to be used for programming the MMU.
     pub fn base_address(&self) -> PhysicalAddress {
-        self.lvl2.physical_start_address()
+        table_address(self.root)
     }
 }
\ No newline at end of file
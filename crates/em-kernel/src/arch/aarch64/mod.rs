@@ -1,7 +1,11 @@
 mod boot;
+mod esr;
 
 pub mod sync;
 pub mod cpu;
 pub mod time;
 pub mod exception;
+pub mod interrupt;
 pub mod memory;
+pub mod percpu;
+pub mod smp;
@@ -0,0 +1,18 @@
+use aarch64_cpu::registers::TPIDR_EL1;
+use tock_registers::interfaces::{Readable, Writeable};
+
+/// The offset from a per-CPU variable's template to the executing core's copy of it.
+#[inline(always)]
+pub fn offset() -> usize {
+    TPIDR_EL1.get() as usize
+}
+
+/// Set the offset returned by [`offset`] on the executing core.
+///
+/// # Safety
+///
+/// - The offset must point at a per-CPU area that belongs to the executing core.
+#[inline(always)]
+pub unsafe fn set_offset(offset: usize) {
+    TPIDR_EL1.set(offset as u64);
+}
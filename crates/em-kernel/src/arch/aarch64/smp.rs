@@ -0,0 +1,68 @@
+use core::arch::asm;
+
+#[cfg(not(feature = "psci"))]
+use aarch64_cpu::asm::{self as cpu_asm, barrier};
+
+use crate::{board, error::Error, memory::{self, VirtualAddress}};
+
+/// The SMC64 function ID of PSCI `CPU_ON`.
+#[cfg(feature = "psci")]
+const PSCI_CPU_ON: u64 = 0xC400_0003;
+
+extern "C" {
+    fn _start_secondary();
+}
+
+/// The physical address of the entry point of the secondary cores, which start with their MMU off.
+fn secondary_entry() -> u64 {
+    memory::linear_virt_to_phys(VirtualAddress(_start_secondary as *const () as usize)).0 as u64
+}
+
+/// Release a secondary core that waits in the firmware's spin table, so that it starts executing
+/// the kernel.
+///
+/// # Safety
+///
+/// - `core` must be a secondary core that has not been released yet.
+#[cfg(not(feature = "psci"))]
+pub unsafe fn release_core(core: usize) -> Result<(), Error> {
+    let mailbox = memory::phys_to_virt(memory::PhysicalAddress(board::cpu::spin_table_mailbox(core))).0;
+    core::ptr::write_volatile(mailbox as *mut u64, secondary_entry());
+
+    // The core polls the mailbox with its MMU and caches off, so the entry address must be
+    // written back to memory before waking it up.
+    asm!("dc civac, {}", in(reg) mailbox, options(nostack, preserves_flags));
+    barrier::dsb(barrier::SY);
+
+    cpu_asm::sev();
+    Ok(())
+}
+
+/// Power on a secondary core through the PSCI firmware interface, so that it starts executing the
+/// kernel. The firmware is called with `smc`.
+///
+/// # Safety
+///
+/// - `core` must be a secondary core that has not been released yet.
+#[cfg(feature = "psci")]
+pub unsafe fn release_core(core: usize) -> Result<(), Error> {
+    assert!(core < board::cpu::NUM_CORES);
+
+    // The target is identified by its MPIDR_EL1 affinity fields, of which only level 0 is used.
+    let mut result = PSCI_CPU_ON;
+    asm!(
+        "smc #0",
+        inout("x0") result,
+        in("x1") core as u64,
+        in("x2") secondary_entry(),
+        in("x3") 0u64,
+        clobber_abi("C"),
+        options(nostack),
+    );
+
+    match result as i64 {
+        0 => Ok(()),
+        -4 => Err("PSCI CPU_ON: core already on".into()),
+        _ => Err("PSCI CPU_ON failed".into()),
+    }
+}
@@ -1,12 +1,43 @@
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicU32, Ordering},
+};
 
+#[cfg(debug_assertions)]
+use core::{panic::Location, sync::atomic::AtomicUsize};
+
+use aarch64_cpu::{asm, registers::SCTLR_EL1};
+use tock_registers::interfaces::Readable;
+
+#[cfg(debug_assertions)]
+use super::cpu;
+
+/// A ticket spinlock.
+///
+/// Cores waiting for the lock are served in the order they arrived, and park in `wfe` until the
+/// holder signals the release with `sev`. The ticket counters are updated with the atomic
+/// instructions of the target: exclusive load/store pairs, or LSE atomics where available.
 pub struct Mutex<T>
 where
     T: ?Sized,
 {
+    next_ticket: AtomicU32,
+    now_serving: AtomicU32,
+
+    /// The core holding the lock, or `NO_HOLDER`.
+    #[cfg(debug_assertions)]
+    holder: AtomicUsize,
+
+    /// Where the holder acquired the lock. Only accessed by the holder.
+    #[cfg(debug_assertions)]
+    holder_location: UnsafeCell<Option<&'static Location<'static>>>,
+
     data: UnsafeCell<T>,
 }
 
+#[cfg(debug_assertions)]
+const NO_HOLDER: usize = usize::MAX;
+
 unsafe impl<T> Send for Mutex<T> where T: ?Sized + Send {}
 unsafe impl<T> Sync for Mutex<T> where T: ?Sized + Send {}
 
@@ -14,14 +45,219 @@ impl<T> Mutex<T> {
     /// Create an instance.
     pub const fn new(data: T) -> Self {
         Self {
+            next_ticket: AtomicU32::new(0),
+            now_serving: AtomicU32::new(0),
+            #[cfg(debug_assertions)]
+            holder: AtomicUsize::new(NO_HOLDER),
+            #[cfg(debug_assertions)]
+            holder_location: UnsafeCell::new(None),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
+impl<T> Mutex<T>
+where
+    T: ?Sized,
+{
+    #[track_caller]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        // Exclusive accesses are only guaranteed to work on cacheable memory, and all memory is
+        // Device memory while the MMU is off. Only the boot core runs at that point, so there is
+        // nothing to exclude.
+        if !mmu_enabled() {
+            let data = unsafe { &mut *self.data.get() };
+            return f(data);
+        }
+
+        self.acquire();
+        let result = f(unsafe { &mut *self.data.get() });
+        self.release();
+
+        result
+    }
+
+    #[track_caller]
+    fn acquire(&self) {
+        #[cfg(debug_assertions)]
+        self.check_recursion();
+
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+
+        // The event register is set by any `sev` since the last `wfe`, so a release between the
+        // load and the `wfe` does not get lost.
+        while self.now_serving.load(Ordering::Acquire) != ticket {
+            asm::wfe();
+        }
+
+        #[cfg(debug_assertions)]
+        {
+            self.holder.store(cpu::core_id(), Ordering::Relaxed);
+            unsafe { *self.holder_location.get() = Some(Location::caller()) };
+        }
+    }
+
+    fn release(&self) {
+        #[cfg(debug_assertions)]
+        self.holder.store(NO_HOLDER, Ordering::Relaxed);
+
+        self.now_serving.fetch_add(1, Ordering::Release);
+        signal_release();
+    }
+
+    /// Panics if the executing core already holds the lock, which would otherwise deadlock.
+    #[cfg(debug_assertions)]
+    #[track_caller]
+    fn check_recursion(&self) {
+        if self.holder.load(Ordering::Relaxed) != cpu::core_id() {
+            return;
+        }
+
+        // The executing core is the holder, so it may access the location.
+        match unsafe { *self.holder_location.get() } {
+            Some(held_at) => panic!(
+                "Recursive lock acquisition at {}, already held from {}",
+                Location::caller(),
+                held_at
+            ),
+            None => panic!("Recursive lock acquisition at {}", Location::caller()),
+        }
+    }
+}
+
+#[inline(always)]
+fn mmu_enabled() -> bool {
+    SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
+}
+
+/// A reader-writer spinlock.
+///
+/// Any number of readers may hold the lock at once, while a writer has exclusive access. A waiting
+/// writer keeps new readers out, so that it can't be starved by a steady stream of readers.
+pub struct RwLock<T>
+where
+    T: ?Sized,
+{
+    /// The number of readers, plus the `WRITER` and `WRITER_WAITING` flags.
+    state: AtomicU32,
+
+    /// The core holding the write lock, or `NO_HOLDER`.
+    #[cfg(debug_assertions)]
+    writer: AtomicUsize,
+
+    data: UnsafeCell<T>,
+}
+
+const WRITER: u32 = 1 << 31;
+const WRITER_WAITING: u32 = 1 << 30;
+
+unsafe impl<T> Send for RwLock<T> where T: ?Sized + Send {}
+unsafe impl<T> Sync for RwLock<T> where T: ?Sized + Send + Sync {}
+
+impl<T> RwLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self {
+            state: AtomicU32::new(0),
+            #[cfg(debug_assertions)]
+            writer: AtomicUsize::new(NO_HOLDER),
             data: UnsafeCell::new(data),
         }
     }
+}
+
+impl<T> RwLock<T>
+where
+    T: ?Sized,
+{
+    #[track_caller]
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        // See `Mutex::lock`.
+        if !mmu_enabled() {
+            return f(unsafe { &*self.data.get() });
+        }
+
+        #[cfg(debug_assertions)]
+        self.check_recursion();
+
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & (WRITER | WRITER_WAITING) == 0
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break;
+            }
+
+            asm::wfe();
+        }
+
+        let result = f(unsafe { &*self.data.get() });
+
+        self.state.fetch_sub(1, Ordering::Release);
+        signal_release();
+
+        result
+    }
+
+    #[track_caller]
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        // See `Mutex::lock`.
+        if !mmu_enabled() {
+            return f(unsafe { &mut *self.data.get() });
+        }
+
+        #[cfg(debug_assertions)]
+        self.check_recursion();
+
+        loop {
+            let state = self.state.load(Ordering::Relaxed);
+            if state & !WRITER_WAITING == 0 {
+                // Taking the lock clears the flag. Other waiting writers set it again.
+                if self
+                    .state
+                    .compare_exchange_weak(state, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+                {
+                    break;
+                }
+            } else if state & WRITER_WAITING == 0 {
+                self.state.fetch_or(WRITER_WAITING, Ordering::Relaxed);
+            }
+
+            asm::wfe();
+        }
+
+        #[cfg(debug_assertions)]
+        self.writer.store(cpu::core_id(), Ordering::Relaxed);
+
+        let result = f(unsafe { &mut *self.data.get() });
+
+        #[cfg(debug_assertions)]
+        self.writer.store(NO_HOLDER, Ordering::Relaxed);
+
+        self.state.fetch_and(!WRITER, Ordering::Release);
+        signal_release();
 
-    pub fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut T) -> R) -> R {
-        // TODO: Implement a real lock!
-        let data = unsafe { &mut *self.data.get() };
+        result
+    }
 
-        f(data)
+    /// Panics if the executing core already holds the write lock, which would otherwise deadlock.
+    #[cfg(debug_assertions)]
+    #[track_caller]
+    fn check_recursion(&self) {
+        if self.writer.load(Ordering::Relaxed) == cpu::core_id() {
+            panic!("Recursive lock acquisition at {}, while holding the write lock", Location::caller());
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Wake the cores waiting in `wfe` after releasing a lock.
+#[inline(always)]
+fn signal_release() {
+    // Make the release visible before waking the waiters.
+    asm::barrier::dsb(asm::barrier::ISHST);
+    asm::sev();
+}
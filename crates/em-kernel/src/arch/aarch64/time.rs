@@ -1,7 +1,7 @@
 use core::{num::{NonZeroU64, NonZeroU32, NonZeroU128}, time::Duration, ops::Div};
 
-use aarch64_cpu::{asm::barrier, registers::CNTPCT_EL0};
-use tock_registers::interfaces::Readable;
+use aarch64_cpu::{asm::barrier, registers::{CNTPCT_EL0, CNTP_CTL_EL0, CNTP_CVAL_EL0}};
+use tock_registers::interfaces::{Readable, Writeable};
 
 use crate::warn;
 
@@ -126,4 +126,20 @@ pub fn spin_for(duration: Duration) {
     //
     // Read CNTPCT_EL0 directly to avoid the ISB that is part of [`read_cntpct`].
     while GenericTimerCounterValue(CNTPCT_EL0.get()) < counter_value_target {}
-}
\ No newline at end of file
+}
+
+/// Arm the EL1 physical timer to raise its IRQ once the uptime reaches `deadline`.
+///
+/// The IRQ stays asserted until the timer is re-armed or disarmed.
+pub fn set_deadline(deadline: Duration) {
+    // A deadline too far in the future to represent will never be reached.
+    let counter_value_target = deadline.try_into().unwrap_or(GenericTimerCounterValue::MAX);
+
+    CNTP_CVAL_EL0.set(counter_value_target.0);
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::CLEAR);
+}
+
+/// Disarm the EL1 physical timer, deasserting its IRQ.
+pub fn clear_deadline() {
+    CNTP_CTL_EL0.write(CNTP_CTL_EL0::ENABLE::CLEAR + CNTP_CTL_EL0::IMASK::SET);
+}
@@ -1,5 +1,6 @@
-#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+// QEMU's virt machine shares the GIC and PL011 drivers of the Raspberry Pi 4.
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4", feature = "board_qemu_virt"))]
 mod raspi;
 
-#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4", feature = "board_qemu_virt"))]
 pub use raspi::*;
\ No newline at end of file
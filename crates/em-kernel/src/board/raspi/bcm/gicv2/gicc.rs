@@ -0,0 +1,108 @@
+//! GICv2 CPU Interface.
+//!
+//! Every core has its own CPU interface at the same address, through which it acknowledges and
+//! completes the IRQs the distributor forwarded to it.
+
+use tock_registers::{register_bitfields, register_structs, registers::{ReadOnly, ReadWrite, WriteOnly}, interfaces::{Readable, Writeable}};
+
+use crate::{board::bcm::MMIODerefWrapper, error::Error};
+
+// Register descriptions taken from "ARM Generic Interrupt Controller Architecture Specification,
+// version 2.0", section 4.4.
+register_bitfields! {
+    u32,
+
+    /// CPU Interface Control Register.
+    CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Priority Mask Register.
+    PMR [
+        /// Only IRQs with a higher priority (lower value) than the mask are signaled to the core.
+        Priority OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Interrupt Acknowledge Register.
+    IAR [
+        InterruptID OFFSET(0) NUMBITS(10) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
+        (0x004 => PMR: ReadWrite<u32, PMR::Register>),
+        (0x008 => _reserved1),
+        (0x00C => IAR: ReadOnly<u32, IAR::Register>),
+        (0x010 => EOIR: WriteOnly<u32>),
+        (0x014 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// IRQ numbers from this value on are reported when no IRQ is pending.
+const SPURIOUS_IRQ_START: usize = 1020;
+
+pub struct GICC {
+    registers: Registers,
+}
+
+impl GICC {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// Maps the registers. Must be called before any other method.
+    pub fn map(&self) -> Result<(), Error> {
+        self.registers.map()
+    }
+
+    /// Signals IRQs of every priority to the executing core.
+    pub fn accept_all_priorities(&self) {
+        self.registers.PMR.write(PMR::Priority.val(0xFF));
+    }
+
+    /// Enables the CPU interface of the executing core.
+    pub fn enable(&self) {
+        self.registers.CTLR.write(CTLR::Enable::SET);
+    }
+
+    /// Acknowledges the highest-priority pending IRQ, which becomes active until it is completed.
+    ///
+    /// Returns `None` if no IRQ is pending.
+    pub fn acknowledge(&self) -> Option<AcknowledgedIrq> {
+        let iar = self.registers.IAR.extract();
+
+        if (iar.read(IAR::InterruptID) as usize) < SPURIOUS_IRQ_START {
+            Some(AcknowledgedIrq(iar.get()))
+        } else {
+            None
+        }
+    }
+
+    /// Completes an IRQ previously returned by `acknowledge`.
+    pub fn end_of_interrupt(&self, irq: AcknowledgedIrq) {
+        // The value read from IAR must be written back unmodified, including the source core of SGIs.
+        self.registers.EOIR.set(irq.0);
+    }
+}
+
+/// An IRQ that was acknowledged at the CPU interface, and must be completed by the same core.
+pub struct AcknowledgedIrq(u32);
+
+impl AcknowledgedIrq {
+    /// The number of the IRQ.
+    pub fn number(&self) -> usize {
+        (self.0 & IAR::InterruptID.mask) as usize
+    }
+}
@@ -0,0 +1,172 @@
+//! GICv2 Distributor.
+//!
+//! The distributor prioritizes interrupts and routes them to the CPU interfaces of the cores.
+//! Registers for SGIs and PPIs (IRQ numbers 0-31) are banked: every core sees its own copy.
+
+use tock_registers::{register_bitfields, register_structs, registers::{ReadOnly, ReadWrite, WriteOnly}, interfaces::{Readable, Writeable}};
+
+use crate::{error::Error, board::bcm::MMIODerefWrapper, sync::IrqSafeMutex};
+
+use super::NUM_IRQS;
+
+// Register descriptions taken from "ARM Generic Interrupt Controller Architecture Specification,
+// version 2.0", section 4.3.
+register_bitfields! {
+    u32,
+
+    /// Distributor Control Register.
+    CTLR [
+        Enable OFFSET(0) NUMBITS(1) []
+    ],
+
+    /// Interrupt Controller Type Register.
+    TYPER [
+        /// The number of implemented IRQs is 32 * (ITLinesNumber + 1).
+        ITLinesNumber OFFSET(0) NUMBITS(5) []
+    ],
+
+    /// Software Generated Interrupt Register.
+    SGIR [
+        TargetListFilter OFFSET(24) NUMBITS(2) [
+            TargetList = 0b00
+        ],
+
+        CPUTargetList OFFSET(16) NUMBITS(8) [],
+
+        SGIINTID OFFSET(0) NUMBITS(4) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
+        (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
+        (0x008 => _reserved1),
+        (0x100 => ISENABLER: [ReadWrite<u32>; 32]),
+        (0x180 => ICENABLER: [ReadWrite<u32>; 32]),
+        (0x200 => _reserved2),
+        (0x400 => IPRIORITYR: [ReadWrite<u8>; 1020]),
+        (0x7FC => _reserved3),
+        (0x800 => ITARGETSR: [ReadWrite<u8>; 1020]),
+        (0xBFC => _reserved4),
+        (0xC00 => ICFGR: [ReadWrite<u32>; 64]),
+        (0xD00 => _reserved5),
+        (0xF00 => SGIR: WriteOnly<u32, SGIR::Register>),
+        (0xF04 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+pub struct GICD {
+    registers: Registers,
+
+    /// Serializes read-modify-write accesses to the configuration registers.
+    config_lock: IrqSafeMutex<()>,
+}
+
+impl GICD {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+            config_lock: IrqSafeMutex::new(()),
+        }
+    }
+
+    /// Maps the registers. Must be called before any other method.
+    pub fn map(&self) -> Result<(), Error> {
+        self.registers.map()
+    }
+
+    /// The number of IRQs implemented by this distributor, including SGIs and PPIs.
+    pub fn num_irqs(&self) -> usize {
+        let implemented = ((self.registers.TYPER.read(TYPER::ITLinesNumber) as usize) + 1) * 32;
+
+        // IRQ numbers 1020-1023 are reserved for special purposes.
+        implemented.min(NUM_IRQS)
+    }
+
+    /// Enables the distributor.
+    pub fn enable(&self) {
+        self.registers.CTLR.write(CTLR::Enable::SET);
+    }
+
+    /// Enables an IRQ. SGIs and PPIs are enabled for the executing core only.
+    pub fn enable_irq(&self, irq: usize) -> Result<(), Error> {
+        self.check_irq(irq)?;
+
+        // Writing a 1 enables the IRQ, zeroes have no effect.
+        self.registers.ISENABLER[irq / 32].set(1 << (irq % 32));
+        Ok(())
+    }
+
+    /// Sets the priority of an IRQ. Lower values are higher priorities.
+    pub fn set_priority(&self, irq: usize, priority: u8) -> Result<(), Error> {
+        self.check_irq(irq)?;
+
+        self.registers.IPRIORITYR[irq].set(priority);
+        Ok(())
+    }
+
+    /// Routes an SPI to the cores given by a bitmask, where bit N is core N.
+    ///
+    /// SGIs and PPIs always target the core they belong to, and can't be routed.
+    pub fn set_targets(&self, irq: usize, core_mask: u8) -> Result<(), Error> {
+        self.check_irq(irq)?;
+        if irq < 32 {
+            return Err("SGIs and PPIs can't be routed".into());
+        }
+
+        self.registers.ITARGETSR[irq].set(core_mask);
+        Ok(())
+    }
+
+    /// Configures an IRQ as level-sensitive, so that it is pending for as long as its source asserts
+    /// it.
+    ///
+    /// SGIs are always edge-triggered, and can't be configured.
+    pub fn set_level_sensitive(&self, irq: usize) -> Result<(), Error> {
+        self.check_irq(irq)?;
+        if irq < 16 {
+            return Err("SGIs can't be configured".into());
+        }
+
+        // Every IRQ has two configuration bits, of which only the upper one is writable.
+        let register = &self.registers.ICFGR[irq / 16];
+        let bit = 1 << ((irq % 16) * 2 + 1);
+
+        self.config_lock.lock(|_| {
+            register.set(register.get() & !bit);
+        });
+
+        Ok(())
+    }
+
+    /// Raises an SGI on a core.
+    pub fn send_sgi(&self, core: usize, sgi: usize) -> Result<(), Error> {
+        if core >= 8 || sgi >= 16 {
+            return Err("SGI target out of range".into());
+        }
+
+        self.registers.SGIR.write(
+            SGIR::TargetListFilter::TargetList
+                + SGIR::CPUTargetList.val(1 << core)
+                + SGIR::SGIINTID.val(sgi as u32),
+        );
+        Ok(())
+    }
+
+    fn check_irq(&self, irq: usize) -> Result<(), Error> {
+        if irq >= self.num_irqs() {
+            return Err("IRQ number out of range".into());
+        }
+
+        Ok(())
+    }
+}
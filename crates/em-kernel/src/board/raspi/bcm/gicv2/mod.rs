@@ -0,0 +1,151 @@
+//! Driver for the ARM Generic Interrupt Controller, version 2 (GIC-400).
+//!
+//! IRQ numbers are the GIC interrupt IDs:
+//!
+//! - 0-15: Software Generated Interrupts (SGIs), used for inter-processor signaling.
+//! - 16-31: Private Peripheral Interrupts (PPIs), such as the per-core timers.
+//! - 32 and up: Shared Peripheral Interrupts (SPIs), for everything else.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    driver::DeviceDriver,
+    error::Error,
+    interrupt::{InterruptController, IrqHandlerDescriptor, IrqHandlerTable, IrqNumber},
+};
+
+use self::{gicc::GICC, gicd::GICD};
+
+mod gicc;
+mod gicd;
+
+/// The largest number of IRQs supported by the driver. The GIC-400 implements at most 480 SPIs.
+pub const NUM_IRQS: usize = 512;
+
+/// The priority assigned to every IRQ during initialization.
+const DEFAULT_PRIORITY: u8 = 0xA0;
+
+/// The IRQ number raised by [`InterruptController::send_ipi`].
+pub const IPI_IRQ: IrqNumber = sgi(0);
+
+/// The IRQ number of a Software Generated Interrupt.
+pub const fn sgi(irq: usize) -> IrqNumber {
+    assert!(irq < 16);
+    IrqNumber(irq)
+}
+
+/// The IRQ number of a Private Peripheral Interrupt.
+pub const fn ppi(irq: usize) -> IrqNumber {
+    assert!(irq < 16);
+    IrqNumber(16 + irq)
+}
+
+/// The IRQ number of a Shared Peripheral Interrupt.
+pub const fn spi(irq: usize) -> IrqNumber {
+    assert!(irq < NUM_IRQS - 32);
+    IrqNumber(32 + irq)
+}
+
+pub struct GICv2 {
+    gicd: GICD,
+    gicc: GICC,
+    handlers: IrqHandlerTable<NUM_IRQS>,
+
+    /// The SGIs and PPIs enabled on the boot core, where bit N is IRQ N.
+    banked_enabled: AtomicU32,
+}
+
+impl GICv2 {
+    pub const NAME: &'static str = "GICv2 (GIC-400)";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct physical MMIO start addresses.
+    pub const unsafe fn new(gicd_mmio_start_addr: usize, gicc_mmio_start_addr: usize) -> Self {
+        Self {
+            gicd: GICD::new(gicd_mmio_start_addr),
+            gicc: GICC::new(gicc_mmio_start_addr),
+            handlers: IrqHandlerTable::new(),
+            banked_enabled: AtomicU32::new(0),
+        }
+    }
+
+    /// Sets up the banked registers of the executing core, and enables its CPU interface.
+    fn init_this_core(&self) -> Result<(), Error> {
+        for irq in 0..32 {
+            self.gicd.set_priority(irq, DEFAULT_PRIORITY)?;
+        }
+
+        self.gicc.accept_all_priorities();
+        self.gicc.enable();
+
+        Ok(())
+    }
+}
+
+impl DeviceDriver for GICv2 {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    unsafe fn init(&self) -> Result<(), Error> {
+        self.gicd.map()?;
+        self.gicc.map()?;
+
+        // Start from a known configuration: every IRQ at the same priority, and every SPI
+        // level-sensitive and routed to the boot core.
+        for irq in 32..self.gicd.num_irqs() {
+            self.gicd.set_priority(irq, DEFAULT_PRIORITY)?;
+            self.gicd.set_targets(irq, 0b1)?;
+            self.gicd.set_level_sensitive(irq)?;
+        }
+
+        self.gicd.enable();
+
+        self.init_this_core()
+    }
+}
+
+impl InterruptController for GICv2 {
+    fn register_handler(&self, descriptor: IrqHandlerDescriptor) -> Result<(), Error> {
+        self.handlers.insert(descriptor)
+    }
+
+    fn enable(&self, irq: IrqNumber) -> Result<(), Error> {
+        self.gicd.enable_irq(irq.0)?;
+
+        if irq.0 < 32 {
+            self.banked_enabled.fetch_or(1 << irq.0, Ordering::Relaxed);
+        }
+
+        Ok(())
+    }
+
+    fn dispatch(&self) {
+        while let Some(pending) = self.gicc.acknowledge() {
+            self.handlers.handle(IrqNumber(pending.number()));
+            self.gicc.end_of_interrupt(pending);
+        }
+    }
+
+    fn init_secondary_core(&self) -> Result<(), Error> {
+        self.init_this_core()?;
+
+        let banked_enabled = self.banked_enabled.load(Ordering::Relaxed);
+        for irq in (0..32).filter(|x| banked_enabled & (1 << x) != 0) {
+            self.gicd.enable_irq(irq)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_ipi(&self, core: usize) -> Result<(), Error> {
+        self.gicd.send_sgi(core, IPI_IRQ.0)
+    }
+
+    fn print_handlers(&self) {
+        self.handlers.print();
+    }
+}
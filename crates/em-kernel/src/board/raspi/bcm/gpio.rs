@@ -1,6 +1,6 @@
 use tock_registers::{register_bitfields, register_structs, registers::ReadWrite, interfaces::ReadWriteable};
 
-use crate::{sync::Mutex, driver::DeviceDriver};
+use crate::{sync::Mutex, driver::DeviceDriver, error::Error};
 
 use super::MMIODerefWrapper;
 
@@ -97,7 +97,7 @@ impl GPIOInner {
     ///
     /// # Safety
     ///
-    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a correct physical MMIO start address.
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
@@ -170,7 +170,7 @@ impl GPIO {
     ///
     /// # Safety
     ///
-    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a correct physical MMIO start address.
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             inner: Mutex::new(GPIOInner::new(mmio_start_addr)),
@@ -187,4 +187,8 @@ impl DeviceDriver for GPIO {
     fn name(&self) -> &'static str {
         Self::NAME
     }
+
+    unsafe fn init(&self) -> Result<(), Error> {
+        self.inner.lock(|inner| inner.registers.map())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,104 @@
+use tock_registers::{register_structs, registers::{ReadOnly, ReadWrite, WriteOnly}, interfaces::{Readable, Writeable}};
+
+use crate::{error::Error, board::bcm::MMIODerefWrapper, sync::IrqSafeMutex};
+
+// Register descriptions taken from "Quad-A7 Control" (BCM2836 ARM-local peripherals), which is
+// also used by the BCM2837.
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x40 => CORE_TIMER_INTERRUPT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x50 => CORE_MAILBOX_INTERRUPT_CONTROL: [ReadWrite<u32>; 4]),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => _reserved2),
+        (0x80 => CORE_MAILBOX_SET: [WriteOnly<u32>; 16]),
+        (0xC0 => CORE_MAILBOX_CLEAR: [ReadWrite<u32>; 16]),
+        (0x100 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The number of per-core IRQ sources, as reported by the core IRQ source registers.
+pub const NUM_IRQS: usize = 12;
+
+/// The local IRQ source that signals a pending GPU peripheral IRQ.
+pub const GPU_IRQ: usize = 8;
+
+/// The local IRQ source of the first mailbox. Mailboxes 0-3 are sources 4-7.
+pub const MAILBOX_IRQ_BASE: usize = 4;
+
+/// The number of cores served by this controller.
+const NUM_CORES: usize = 4;
+
+/// The per-core ("ARM local") interrupt controller.
+///
+/// All sources are banked per core: enabling a source enables it for the executing core only.
+pub struct LocalInterruptController {
+    registers: IrqSafeMutex<Registers>,
+}
+
+impl LocalInterruptController {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: IrqSafeMutex::new(Registers::new(mmio_start_addr)),
+        }
+    }
+
+    /// Maps the registers. Must be called before any other method.
+    pub fn map(&self) -> Result<(), Error> {
+        self.registers.lock(|registers| registers.map())
+    }
+
+    /// Enables a local IRQ for a core.
+    pub fn enable(&self, core: usize, irq: usize) -> Result<(), Error> {
+        if core >= NUM_CORES {
+            return Err("Core out of range".into());
+        }
+
+        self.registers.lock(|registers| {
+            let register = match irq {
+                0..=3 => &registers.CORE_TIMER_INTERRUPT_CONTROL[core],
+                4..=7 => &registers.CORE_MAILBOX_INTERRUPT_CONTROL[core],
+                // The GPU IRQ is routed to core 0 by the firmware, and always enabled there.
+                GPU_IRQ => return Err("The GPU IRQ cannot be controlled".into()),
+                _ => return Err("Unsupported local IRQ".into()),
+            };
+
+            // In both registers, bits 0-3 enable the IRQ of the respective timer or mailbox.
+            let bit = 1 << (irq % 4);
+            let current = register.get();
+            register.set(current | bit);
+
+            Ok(())
+        })
+    }
+
+    /// Sets all bits of a mailbox of a core, raising its IRQ.
+    pub fn set_mailbox(&self, core: usize, mailbox: usize) -> Result<(), Error> {
+        if core >= NUM_CORES || mailbox >= 4 {
+            return Err("Mailbox out of range".into());
+        }
+
+        // The mailboxes are write-1-to-set, so no locking is required.
+        self.registers.lock(|registers| registers.CORE_MAILBOX_SET[core * 4 + mailbox].set(u32::MAX));
+        Ok(())
+    }
+
+    /// Clears all bits of a mailbox of a core, lowering its IRQ.
+    pub fn clear_mailbox(&self, core: usize, mailbox: usize) {
+        let index = (core % NUM_CORES) * 4 + mailbox % 4;
+        self.registers.lock(|registers| registers.CORE_MAILBOX_CLEAR[index].set(u32::MAX));
+    }
+
+    /// A bitmask of the pending local IRQs of a core, where bit N is local IRQ N.
+    pub fn pending(&self, core: usize) -> u32 {
+        self.registers.lock(|registers| registers.CORE_IRQ_SOURCE[core % NUM_CORES].get())
+    }
+}
@@ -0,0 +1,157 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use crate::{
+    arch::cpu,
+    driver::DeviceDriver,
+    error::Error,
+    interrupt::{InterruptController, IrqHandlerDescriptor, IrqHandlerTable, IrqNumber},
+};
+
+use self::{local_ic::LocalInterruptController, peripheral_ic::PeripheralInterruptController};
+
+mod local_ic;
+mod peripheral_ic;
+
+/// The first IRQ number used for GPU peripheral IRQs.
+///
+/// IRQ numbers below this are the per-core sources of the local interrupt controller.
+const PERIPHERAL_IRQ_BASE: usize = 32;
+
+const NUM_IRQS: usize = PERIPHERAL_IRQ_BASE + peripheral_ic::NUM_IRQS;
+
+/// The IRQ number of a per-core source of the local interrupt controller.
+pub const fn local_irq(source: usize) -> IrqNumber {
+    assert!(source < local_ic::NUM_IRQS);
+    IrqNumber(source)
+}
+
+/// The IRQ number raised by [`InterruptController::send_ipi`].
+pub const IPI_IRQ: IrqNumber = local_irq(local_ic::MAILBOX_IRQ_BASE);
+
+/// The IRQ number of a GPU peripheral IRQ.
+pub const fn peripheral_irq(irq: usize) -> IrqNumber {
+    assert!(irq < peripheral_ic::NUM_IRQS);
+    IrqNumber(PERIPHERAL_IRQ_BASE + irq)
+}
+
+/// The interrupt controller of the BCM2837.
+///
+/// Combines the per-core local interrupt controller, which receives IRQs from the core timers and
+/// mailboxes, with the GPU peripheral interrupt controller, whose IRQs arrive at the local
+/// controller of core 0.
+pub struct BcmInterruptController {
+    local: LocalInterruptController,
+    peripheral: PeripheralInterruptController,
+    handlers: IrqHandlerTable<NUM_IRQS>,
+
+    /// The local IRQs enabled on the boot core, where bit N is local IRQ N.
+    local_enabled: AtomicU32,
+}
+
+impl BcmInterruptController {
+    pub const NAME: &'static str = "BCM Interrupt Controller";
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide correct physical MMIO start addresses.
+    pub const unsafe fn new(local_mmio_start_addr: usize, peripheral_mmio_start_addr: usize) -> Self {
+        Self {
+            local: LocalInterruptController::new(local_mmio_start_addr),
+            peripheral: PeripheralInterruptController::new(peripheral_mmio_start_addr),
+            handlers: IrqHandlerTable::new(),
+            local_enabled: AtomicU32::new(0),
+        }
+    }
+
+}
+
+impl DeviceDriver for BcmInterruptController {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    unsafe fn init(&self) -> Result<(), Error> {
+        self.local.map()?;
+        self.peripheral.map()
+    }
+}
+
+impl InterruptController for BcmInterruptController {
+    fn register_handler(&self, descriptor: IrqHandlerDescriptor) -> Result<(), Error> {
+        self.handlers.insert(descriptor)
+    }
+
+    fn enable(&self, irq: IrqNumber) -> Result<(), Error> {
+        match irq.0 {
+            n if n < local_ic::NUM_IRQS => {
+                self.local.enable(cpu::core_id(), n)?;
+                self.local_enabled.fetch_or(1 << n, Ordering::Relaxed);
+                Ok(())
+            }
+            n if (PERIPHERAL_IRQ_BASE..NUM_IRQS).contains(&n) => {
+                self.peripheral.enable(n - PERIPHERAL_IRQ_BASE)
+            }
+            _ => Err("IRQ number out of range".into()),
+        }
+    }
+
+    fn dispatch(&self) {
+        let local_pending = self.local.pending(cpu::core_id());
+
+        for source in BitIter(local_pending as u64) {
+            if source == local_ic::GPU_IRQ {
+                continue;
+            }
+
+            // Mailbox IRQs stay raised until the mailbox is cleared. Clearing it before handling
+            // ensures that no IPI sent while handling gets lost.
+            if (local_ic::MAILBOX_IRQ_BASE..local_ic::MAILBOX_IRQ_BASE + 4).contains(&source) {
+                self.local.clear_mailbox(cpu::core_id(), source - local_ic::MAILBOX_IRQ_BASE);
+            }
+
+            self.handlers.handle(local_irq(source));
+        }
+
+        if local_pending & (1 << local_ic::GPU_IRQ) != 0 {
+            for irq in BitIter(self.peripheral.pending()) {
+                self.handlers.handle(peripheral_irq(irq));
+            }
+        }
+    }
+
+    fn init_secondary_core(&self) -> Result<(), Error> {
+        for irq in BitIter(self.local_enabled.load(Ordering::Relaxed) as u64) {
+            self.local.enable(cpu::core_id(), irq)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_ipi(&self, core: usize) -> Result<(), Error> {
+        self.local.set_mailbox(core, IPI_IRQ.0 - local_ic::MAILBOX_IRQ_BASE)
+    }
+
+    fn print_handlers(&self) {
+        self.handlers.print();
+    }
+}
+
+/// Iterates over the indices of the set bits of a mask, lowest first.
+struct BitIter(u64);
+
+impl Iterator for BitIter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.0 == 0 {
+            return None;
+        }
+
+        let index = self.0.trailing_zeros() as usize;
+        self.0 &= self.0 - 1;
+
+        Some(index)
+    }
+}
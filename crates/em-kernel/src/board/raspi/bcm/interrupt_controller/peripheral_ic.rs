@@ -0,0 +1,70 @@
+use tock_registers::{register_structs, registers::{ReadOnly, WriteOnly}, interfaces::{Readable, Writeable}};
+
+use crate::{error::Error, board::bcm::MMIODerefWrapper};
+
+// Register descriptions taken from "BCM2837 ARM Peripherals" section 7.5.
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x04 => PENDING_1: ReadOnly<u32>),
+        (0x08 => PENDING_2: ReadOnly<u32>),
+        (0x0C => _reserved2),
+        (0x10 => ENABLE_1: WriteOnly<u32>),
+        (0x14 => ENABLE_2: WriteOnly<u32>),
+        (0x18 => _reserved3),
+        (0x1C => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => @END),
+    }
+}
+
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The number of GPU peripheral IRQs handled by this controller.
+pub const NUM_IRQS: usize = 64;
+
+/// The interrupt controller for the GPU peripherals.
+///
+/// The enable and disable registers are write-1-to-set, so no locking is required.
+pub struct PeripheralInterruptController {
+    registers: Registers,
+}
+
+impl PeripheralInterruptController {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide a correct physical MMIO start address.
+    pub const unsafe fn new(mmio_start_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(mmio_start_addr),
+        }
+    }
+
+    /// Maps the registers. Must be called before any other method.
+    pub fn map(&self) -> Result<(), Error> {
+        self.registers.map()
+    }
+
+    /// Enables a peripheral IRQ.
+    pub fn enable(&self, irq: usize) -> Result<(), Error> {
+        let (register, mask) = match irq {
+            0..=31 => (&self.registers.ENABLE_1, 1 << irq),
+            32..=63 => (&self.registers.ENABLE_2, 1 << (irq - 32)),
+            _ => return Err("Peripheral IRQ out of range".into()),
+        };
+
+        register.set(mask);
+        Ok(())
+    }
+
+    /// A bitmask of the pending peripheral IRQs, where bit N is peripheral IRQ N.
+    pub fn pending(&self) -> u64 {
+        let lower = self.registers.PENDING_1.get() as u64;
+        let upper = self.registers.PENDING_2.get() as u64;
+
+        (upper << 32) | lower
+    }
+}
@@ -1,27 +1,58 @@
-use core::marker::PhantomData;
+use core::{
+    marker::PhantomData,
+    mem,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
+use crate::{error::Error, memory::{self, PhysicalAddress}};
+
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
 pub mod gpio;
 pub mod pl011_uart;
 
+#[cfg(feature = "board_raspi3")]
+pub mod interrupt_controller;
+
+#[cfg(any(feature = "board_raspi4", feature = "board_qemu_virt"))]
+pub mod gicv2;
+
 pub struct MMIODerefWrapper<T> {
-    start_addr: usize,
+    phys_start_addr: usize,
+
+    /// Where the registers are mapped, or 0 until [`MMIODerefWrapper::map`] is called.
+    start_addr: AtomicUsize,
+
     phantom: PhantomData<fn() -> T>,
 }
 
 impl<T> MMIODerefWrapper<T> {
-    /// Create an instance.
-    pub const unsafe fn new(start_addr: usize) -> Self {
+    /// Create an instance for registers at a physical address.
+    pub const unsafe fn new(phys_start_addr: usize) -> Self {
         Self {
-            start_addr,
+            phys_start_addr,
+            start_addr: AtomicUsize::new(0),
             phantom: PhantomData,
         }
     }
+
+    /// Map the registers into the kernel's address space. Must be called before they are accessed.
+    pub fn map(&self) -> Result<(), Error> {
+        let virt_addr = memory::ioremap(PhysicalAddress(self.phys_start_addr), mem::size_of::<T>())?;
+        self.start_addr.store(virt_addr.0, Ordering::Relaxed);
+
+        Ok(())
+    }
 }
 
 impl<T> core::ops::Deref for MMIODerefWrapper<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
-        unsafe { &*(self.start_addr as *const _) }
+        let start_addr = self.start_addr.load(Ordering::Relaxed);
+
+        // Checked in release builds as well, as an access would otherwise go to address 0.
+        assert!(start_addr != 0, "MMIO registers at {:#x} accessed before they were mapped", self.phys_start_addr);
+
+        unsafe { &*(start_addr as *const _) }
     }
 }
\ No newline at end of file
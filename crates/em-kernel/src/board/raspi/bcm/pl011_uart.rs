@@ -1,6 +1,6 @@
 use tock_registers::{register_bitfields, register_structs, registers::{ReadWrite, ReadOnly, WriteOnly}, interfaces::{Writeable, Readable}};
 
-use crate::{sync::{Mutex, self}, driver::DeviceDriver, console, error::Error};
+use crate::{sync::{IrqSafeMutex, self}, driver::DeviceDriver, console, error::Error, interrupt::IrqHandler};
 
 use super::MMIODerefWrapper;
 
@@ -114,6 +114,52 @@ register_bitfields! {
         ]
     ],
 
+    /// Interrupt FIFO Level Select Register.
+    IFLS [
+        /// Receive interrupt FIFO level select. The trigger points for the receive interrupt are as
+        /// follows.
+        RXIFLSEL OFFSET(3) NUMBITS(5) [
+            OneEigth = 0b000,
+            OneQuarter = 0b001,
+            OneHalf = 0b010,
+            ThreeQuarters = 0b011,
+            SevenEights = 0b100
+        ]
+    ],
+
+    /// Interrupt Mask Set/Clear Register.
+    IMSC [
+        /// Receive timeout interrupt mask. A read returns the current mask for the UARTRTINTR
+        /// interrupt.
+        ///
+        /// - On a write of 1, the mask of the UARTRTINTR interrupt is set.
+        /// - A write of 0 clears the mask.
+        RTIM OFFSET(6) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ],
+
+        /// Receive interrupt mask. A read returns the current mask for the UARTRXINTR interrupt.
+        ///
+        /// - On a write of 1, the mask of the UARTRXINTR interrupt is set.
+        /// - A write of 0 clears the mask.
+        RXIM OFFSET(4) NUMBITS(1) [
+            Disabled = 0,
+            Enabled = 1
+        ]
+    ],
+
+    /// Masked Interrupt Status Register.
+    MIS [
+        /// Receive timeout masked interrupt status. Returns the masked interrupt state of the
+        /// UARTRTINTR interrupt.
+        RTMIS OFFSET(6) NUMBITS(1) [],
+
+        /// Receive masked interrupt status. Returns the masked interrupt state of the UARTRXINTR
+        /// interrupt.
+        RXMIS OFFSET(4) NUMBITS(1) []
+    ],
+
     /// Interrupt Clear Register.
     ICR [
         /// Meta field for all pending interrupts.
@@ -132,7 +178,10 @@ register_structs! {
         (0x28 => FBRD: WriteOnly<u32, FBRD::Register>),
         (0x2c => LCR_H: WriteOnly<u32, LCR_H::Register>),
         (0x30 => CR: WriteOnly<u32, CR::Register>),
-        (0x34 => _reserved3),
+        (0x34 => IFLS: ReadWrite<u32, IFLS::Register>),
+        (0x38 => IMSC: ReadWrite<u32, IMSC::Register>),
+        (0x3C => _reserved3),
+        (0x40 => MIS: ReadOnly<u32, MIS::Register>),
         (0x44 => ICR: WriteOnly<u32, ICR::Register>),
         (0x48 => @END),
     }
@@ -153,7 +202,7 @@ struct PL011UartInner {
 }
 
 pub struct PL011Uart {
-    inner: Mutex<PL011UartInner>,
+    inner: IrqSafeMutex<PL011UartInner>,
 }
 
 impl PL011UartInner {
@@ -161,7 +210,7 @@ impl PL011UartInner {
     ///
     /// # Safety
     ///
-    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a correct physical MMIO start address.
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
             registers: Registers::new(mmio_start_addr),
@@ -217,6 +266,14 @@ impl PL011UartInner {
             .LCR_H
             .write(LCR_H::WLEN::EightBit + LCR_H::FEN::FifosEnabled);
 
+        // Set RX FIFO fill level at 1/8.
+        self.registers.IFLS.write(IFLS::RXIFLSEL::OneEigth);
+
+        // Enable RX IRQ + RX timeout IRQ.
+        self.registers
+            .IMSC
+            .write(IMSC::RXIM::Enabled + IMSC::RTIM::Enabled);
+
         // Turn the UART on.
         self.registers
             .CR
@@ -285,10 +342,10 @@ impl PL011Uart {
     ///
     /// # Safety
     ///
-    /// - The user must ensure to provide a correct MMIO start address.
+    /// - The user must ensure to provide a correct physical MMIO start address.
     pub const unsafe fn new(mmio_start_addr: usize) -> Self {
         Self {
-            inner: Mutex::new(PL011UartInner::new(mmio_start_addr)),
+            inner: IrqSafeMutex::new(PL011UartInner::new(mmio_start_addr)),
         }
     }
 }
@@ -299,7 +356,35 @@ impl DeviceDriver for PL011Uart {
     }
 
     unsafe fn init(&self) -> Result<(), Error> {
-        self.inner.lock(|inner| inner.init());
+        self.inner.lock(|inner| {
+            inner.registers.map()?;
+            inner.init();
+            Ok(())
+        })
+    }
+
+    fn irq_handler(&'static self) -> Option<&'static (dyn IrqHandler + Sync)> {
+        Some(self)
+    }
+}
+
+impl IrqHandler for PL011Uart {
+    fn handle(&self) -> Result<(), Error> {
+        self.inner.lock(|inner| {
+            let pending = inner.registers.MIS.extract();
+
+            // Clear all pending IRQs.
+            inner.registers.ICR.write(ICR::ALL::CLEAR);
+
+            // Check for any kind of RX interrupt.
+            if pending.matches_any(MIS::RXMIS::SET + MIS::RTMIS::SET) {
+                // Echo any received characters.
+                while let Some(c) = inner.read_char_converting(BlockingMode::NonBlocking) {
+                    inner.write_char(c)
+                }
+            }
+        });
+
         Ok(())
     }
 }
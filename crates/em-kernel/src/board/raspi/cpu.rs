@@ -1,3 +1,22 @@
 #[no_mangle]
 #[link_section = ".text._start_arguments"]
-pub static BOOT_CORE_ID: u64 = 0;
\ No newline at end of file
+pub static BOOT_CORE_ID: u64 = 0;
+
+/// The number of cores of the SoC. Must match the linker script.
+pub const NUM_CORES: usize = 4;
+
+/// The size of the stack of each secondary core. Must match the linker script.
+pub const SECONDARY_CORE_STACK_SIZE: usize = 64 * 1024;
+
+/// The size of the unmapped guard below each kernel stack. As large as the largest translation
+/// granule, so that it is made of whole pages with any of them. Must match the linker script.
+pub const STACK_GUARD_SIZE: usize = 64 * 1024;
+
+/// The spin-table mailbox of a secondary core, as set up by the firmware.
+///
+/// The core spins with the MMU off until it reads a non-zero entry address from its mailbox.
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+pub const fn spin_table_mailbox(core: usize) -> usize {
+    assert!(core > 0 && core < NUM_CORES);
+    0xD8 + core * 8
+}
@@ -1,10 +1,25 @@
-use crate::{driver, error::Error, console};
+use crate::{driver, error::Error, console, interrupt, time};
 
-use super::{bcm, memory};
+use super::{bcm, memory, irq};
+
+#[cfg(feature = "board_raspi3")]
+pub static INTERRUPT_CONTROLLER: bcm::interrupt_controller::BcmInterruptController = unsafe {
+    bcm::interrupt_controller::BcmInterruptController::new(
+        memory::mmio::LOCAL_IC_START,
+        memory::mmio::PERIPHERAL_IC_START,
+    )
+};
+
+#[cfg(any(feature = "board_raspi4", feature = "board_qemu_virt"))]
+pub static INTERRUPT_CONTROLLER: bcm::gicv2::GICv2 = unsafe {
+    bcm::gicv2::GICv2::new(memory::mmio::GICD_START, memory::mmio::GICC_START)
+};
 
 pub static PL011_UART: bcm::pl011_uart::PL011Uart = unsafe {
     bcm::pl011_uart::PL011Uart::new(memory::mmio::PL011_UART_START)
 };
+
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
 pub static GPIO: bcm::gpio::GPIO = unsafe {
     bcm::gpio::GPIO::new(memory::mmio::GPIO_START)
 };
@@ -17,22 +32,53 @@ fn uart_post_init() -> Result<(), Error> {
 fn uart_init() -> Result<(), Error> {
     let descriptor = driver::DeviceDriverDescriptor::new(
         &PL011_UART,
-        Some(uart_post_init)
+        Some(uart_post_init),
+        Some(irq::PL011_UART),
     );
     driver::manager().install(descriptor);
 
     Ok(())
 }
 
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
 fn gpio_post_init() -> Result<(), Error> {
     GPIO.map_pl011_uart();
     Ok(())
 }
 
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
 fn gpio_init() -> Result<(), Error> {
     let descriptor = driver::DeviceDriverDescriptor::new(
         &GPIO,
-        Some(gpio_post_init)
+        Some(gpio_post_init),
+        None,
+    );
+    driver::manager().install(descriptor);
+
+    Ok(())
+}
+
+fn timer_init() -> Result<(), Error> {
+    let descriptor = driver::DeviceDriverDescriptor::new(
+        time::keeper(),
+        None,
+        Some(irq::ARCH_TIMER),
+    );
+    driver::manager().install(descriptor);
+
+    Ok(())
+}
+
+fn interrupt_controller_post_init() -> Result<(), Error> {
+    interrupt::register_controller(&INTERRUPT_CONTROLLER);
+    Ok(())
+}
+
+fn interrupt_controller_init() -> Result<(), Error> {
+    let descriptor = driver::DeviceDriverDescriptor::new(
+        &INTERRUPT_CONTROLLER,
+        Some(interrupt_controller_post_init),
+        None,
     );
     driver::manager().install(descriptor);
 
@@ -40,8 +86,13 @@ fn gpio_init() -> Result<(), Error> {
 }
 
 pub fn init() -> Result<(), Error> {
+    // The interrupt controller must be up before any driver can register its IRQ handler.
+    interrupt_controller_init()?;
+
     uart_init()?;
+    #[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
     gpio_init()?;
+    timer_init()?;
 
     Ok(())
 }
\ No newline at end of file
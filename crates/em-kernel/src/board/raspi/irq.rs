@@ -0,0 +1,34 @@
+//! IRQ numbers of the board's devices, as understood by the board's interrupt controller.
+
+use crate::interrupt::IrqNumber;
+
+#[cfg(feature = "board_raspi3")]
+use super::bcm::interrupt_controller::{local_irq, peripheral_irq};
+
+#[cfg(any(feature = "board_raspi4", feature = "board_qemu_virt"))]
+use super::bcm::gicv2::{ppi, spi};
+
+/// The inter-processor interrupt.
+#[cfg(feature = "board_raspi3")]
+pub use super::bcm::interrupt_controller::IPI_IRQ as IPI;
+
+/// The EL1 physical timer (CNTPNSIRQ) of the executing core.
+#[cfg(feature = "board_raspi3")]
+pub const ARCH_TIMER: IrqNumber = local_irq(1);
+
+#[cfg(feature = "board_raspi3")]
+pub const PL011_UART: IrqNumber = peripheral_irq(57);
+
+/// The inter-processor interrupt.
+#[cfg(any(feature = "board_raspi4", feature = "board_qemu_virt"))]
+pub use super::bcm::gicv2::IPI_IRQ as IPI;
+
+/// The EL1 physical timer of the executing core.
+#[cfg(any(feature = "board_raspi4", feature = "board_qemu_virt"))]
+pub const ARCH_TIMER: IrqNumber = ppi(14);
+
+#[cfg(feature = "board_raspi4")]
+pub const PL011_UART: IrqNumber = spi(121);
+
+#[cfg(feature = "board_qemu_virt")]
+pub const PL011_UART: IrqNumber = spi(1);
@@ -1,26 +1,62 @@
-use core::cell::UnsafeCell;
+use core::{cell::UnsafeCell, ops::Range};
 
-use crate::memory::{KernelVirtualMemoryLayout, TranslationDescriptor, MemoryAttributes, MemoryType, MemoryAccess, Translation, AddressSpace};
+use crate::memory::{self, KERNEL_VIRT_START, VirtualAddress, KernelVirtualMemoryLayout, TranslationDescriptor, MemoryAttributes, MemoryType, MemoryAccess, Shareability, Translation, AddressSpace};
+
+use super::cpu;
 
 // Symbols from the linker script.
 extern "Rust" {
+    static __boot_core_stack_start: UnsafeCell<()>;
+    static __boot_core_stack_end_exclusive: UnsafeCell<()>;
+    static __secondary_core_stacks_start: UnsafeCell<()>;
     static __code_start: UnsafeCell<()>;
     static __code_end_exclusive: UnsafeCell<()>;
+    static __rodata_start: UnsafeCell<()>;
+    static __rodata_end_exclusive: UnsafeCell<()>;
+    static __data_start: UnsafeCell<()>;
+    static __kernel_end_exclusive: UnsafeCell<()>;
 }
 
 // Defines memory layout
-pub const GPIO_OFFSET:         usize = 0x0020_0000;
-pub const UART_OFFSET:         usize = 0x0020_1000;
-const END_INCLUSIVE:       usize = 0xFFFF_FFFF;
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+pub const GPIO_OFFSET: usize = 0x0020_0000;
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+pub const UART_OFFSET: usize = 0x0020_1000;
+const END_INCLUSIVE:   usize = 0xFFFF_FFFF;
+
+/// Physical RAM usable by the kernel.
+///
+/// The firmware places the VideoCore's memory at the top of the first GiB, sized by `gpu_mem` in
+/// `config.txt`. The range below assumes the default of 64 MiB. Any RAM above the first GiB on the
+/// larger Raspberry Pi 4 models is not used yet.
+#[cfg(any(feature = "board_raspi3", feature = "board_raspi4"))]
+pub mod dram {
+    pub const START:         usize = 0x0000_0000;
+    pub const END_EXCLUSIVE: usize = 0x3C00_0000;
+}
+
+/// Physical RAM usable by the kernel.
+///
+/// QEMU's virt machine starts RAM at 1 GiB. The range below assumes it is started with `-m 1G`.
+#[cfg(feature = "board_qemu_virt")]
+pub mod dram {
+    pub const START:         usize = 0x4000_0000;
+    pub const END_EXCLUSIVE: usize = 0x8000_0000;
+}
 
 /// Physical devices.
 #[cfg(feature = "board_raspi3")]
 pub mod mmio {
     use super::*;
 
-    pub const START:            usize =         0x3F00_0000;
-    pub const GPIO_START:       usize = START + GPIO_OFFSET;
-    pub const PL011_UART_START: usize = START + UART_OFFSET;
+    pub const PERIPHERAL_IC_OFFSET: usize = 0x0000_B200;
+
+    pub const START:               usize =         0x3F00_0000;
+    pub const PERIPHERAL_IC_START: usize = START + PERIPHERAL_IC_OFFSET;
+    pub const GPIO_START:          usize = START + GPIO_OFFSET;
+    pub const PL011_UART_START:    usize = START + UART_OFFSET;
+    pub const LOCAL_IC_START:      usize =         0x4000_0000;
+    pub const END_INCLUSIVE:       usize =         0x4003_FFFF;
 }
 
 /// Physical devices.
@@ -31,6 +67,73 @@ pub mod mmio {
     pub const START:            usize =         0xFE00_0000;
     pub const GPIO_START:       usize = START + GPIO_OFFSET;
     pub const PL011_UART_START: usize = START + UART_OFFSET;
+    pub const GICD_START:       usize =         0xFF84_1000;
+    pub const GICC_START:       usize =         0xFF84_2000;
+    pub const END_INCLUSIVE:    usize =         0xFFFF_FFFF;
+}
+
+/// Physical devices.
+///
+/// Everything between the flash at the bottom and RAM at 1 GiB.
+#[cfg(feature = "board_qemu_virt")]
+pub mod mmio {
+    pub const START:            usize = 0x0800_0000;
+    pub const GICD_START:       usize = 0x0800_0000;
+    pub const GICC_START:       usize = 0x0801_0000;
+    pub const PL011_UART_START: usize = 0x0900_0000;
+    pub const END_INCLUSIVE:    usize = 0x3FFF_FFFF;
+}
+
+/// The virtual addresses `ioremap` maps devices at.
+///
+/// The window covers the linear mapping of the physical MMIO range, so that the devices are never
+/// also reachable as normal memory.
+pub mod ioremap {
+    use super::KERNEL_VIRT_START;
+
+    pub const START:         usize = KERNEL_VIRT_START + super::mmio::START;
+    pub const END_INCLUSIVE: usize = KERNEL_VIRT_START + super::mmio::END_INCLUSIVE;
+}
+
+/// The virtual addresses of the kernel's demand-paged memory, see [`memory::region`].
+///
+/// The window covers the linear mapping of physical addresses that hold neither devices nor RAM
+/// that the kernel uses.
+pub mod demand_paged {
+    use super::KERNEL_VIRT_START;
+
+    pub const START:         usize = KERNEL_VIRT_START + 0x8000_0000;
+    pub const END_INCLUSIVE: usize = KERNEL_VIRT_START + 0xBFFF_FFFF;
+}
+
+/// Start page address of the boot core stack.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn boot_core_stack_start() -> usize {
+    unsafe { __boot_core_stack_start.get() as usize }
+}
+
+/// Exclusive end page address of the boot core stack.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn boot_core_stack_end_exclusive() -> usize {
+    unsafe { __boot_core_stack_end_exclusive.get() as usize }
+}
+
+/// Start page address of the secondary core stacks, which begin with the guard of the first one.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn secondary_core_stacks_start() -> usize {
+    unsafe { __secondary_core_stacks_start.get() as usize }
 }
 
 /// Start page address of the code segment.
@@ -52,50 +155,146 @@ fn code_end_exclusive() -> usize {
     unsafe { __code_end_exclusive.get() as usize }
 }
 
+/// Start page address of the read-only data segment.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn rodata_start() -> usize {
+    unsafe { __rodata_start.get() as usize }
+}
+
+/// Exclusive end page address of the read-only data segment.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn rodata_end_exclusive() -> usize {
+    unsafe { __rodata_end_exclusive.get() as usize }
+}
+
+/// Start page address of the data segment, which holds everything writable up to the end of the
+/// kernel image.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn data_start() -> usize {
+    unsafe { __data_start.get() as usize }
+}
+
+/// Exclusive end page address of the kernel image, including the boot core stack below the code,
+/// the BSS with the kernel translation tables, the per-CPU areas and the secondary core stacks.
+///
+/// # Safety
+///
+/// - Value is provided by the linker script and must be trusted as-is.
+#[inline(always)]
+fn kernel_end_exclusive() -> usize {
+    unsafe { __kernel_end_exclusive.get() as usize }
+}
+
+/// The physical RAM occupied by the kernel image, which must never be handed out as free memory.
+pub fn kernel_image_range() -> Range<usize> {
+    dram::START..memory::linear_virt_to_phys(VirtualAddress(kernel_end_exclusive())).0
+}
+
+/// The kernel stack of `core`, not including the guard of [`cpu::STACK_GUARD_SIZE`] bytes below
+/// it.
+///
+/// Must match the stacks `boot.s` sets up.
+pub fn kernel_stack(core: usize) -> Range<usize> {
+    assert!(core < cpu::NUM_CORES);
+
+    if core == cpu::BOOT_CORE_ID as usize {
+        return boot_core_stack_start()..boot_core_stack_end_exclusive();
+    }
+
+    let end = secondary_core_stacks_start() + core * (cpu::STACK_GUARD_SIZE + cpu::SECONDARY_CORE_STACK_SIZE);
+    end - cpu::SECONDARY_CORE_STACK_SIZE..end
+}
+
 /// The virtual memory layout.
 ///
 /// The layout must contain only special ranges, aka anything that is _not_ normal cacheable DRAM.
 /// It is agnostic of the paging granularity that the architecture's MMU will use.
-pub static LAYOUT: KernelVirtualMemoryLayout<3> = KernelVirtualMemoryLayout::new(
-    END_INCLUSIVE,
+///
+/// No memory is both writable and executable: only the code is executable, and it is read-only.
+/// The kernel's data, like the rest of RAM, is writable but not executable.
+pub static LAYOUT: KernelVirtualMemoryLayout<5> = KernelVirtualMemoryLayout::new(
+    KERNEL_VIRT_START + END_INCLUSIVE,
     [
         TranslationDescriptor {
-            name: "Kernel code and RO data",
-            virtual_range: || code_start()..=code_end_exclusive(),
-            translation: Translation::Identity,
+            name: "Kernel code",
+            virtual_range: || code_start()..=code_end_exclusive() - 1,
+            translation: Translation::Linear,
+            attributes: MemoryAttributes {
+                memory_type: MemoryType::Normal,
+                access: MemoryAccess::ReadOnly,
+                executable: true,
+                user_accessible: false,
+                shareability: Shareability::Inner,
+            },
+        },
+        TranslationDescriptor {
+            name: "Kernel RO data",
+            virtual_range: || rodata_start()..=rodata_end_exclusive() - 1,
+            translation: Translation::Linear,
             attributes: MemoryAttributes {
                 memory_type: MemoryType::Normal,
                 access: MemoryAccess::ReadOnly,
                 executable: false,
+                user_accessible: false,
+                shareability: Shareability::Inner,
             },
         },
         TranslationDescriptor {
-            name: "Remapped Device MMIO",
-            virtual_range: || 0x1FFF_0000..=0x1FFF_FFFF,
-            translation: Translation::Offset(mmio::START + 0x20_0000),
+            name: "Kernel data and stacks",
+            virtual_range: || data_start()..=kernel_end_exclusive() - 1,
+            translation: Translation::Linear,
             attributes: MemoryAttributes {
-                memory_type: MemoryType::Device,
+                memory_type: MemoryType::Normal,
                 access: MemoryAccess::ReadWrite,
                 executable: false,
+                user_accessible: false,
+                shareability: Shareability::Inner,
             },
         },
         TranslationDescriptor {
-            name: "Device MMIO",
-            virtual_range: || mmio::START..=END_INCLUSIVE,
-            translation: Translation::Identity,
+            name: "I/O remapping window",
+            virtual_range: || ioremap::START..=ioremap::END_INCLUSIVE,
+            translation: Translation::Unmapped,
             attributes: MemoryAttributes {
                 memory_type: MemoryType::Device,
                 access: MemoryAccess::ReadWrite,
                 executable: false,
+                user_accessible: false,
+                shareability: Shareability::Outer,
+            },
+        },
+        TranslationDescriptor {
+            name: "Demand-paged window",
+            virtual_range: || demand_paged::START..=demand_paged::END_INCLUSIVE,
+            translation: Translation::Unmapped,
+            attributes: MemoryAttributes {
+                memory_type: MemoryType::Normal,
+                access: MemoryAccess::ReadWrite,
+                executable: false,
+                user_accessible: false,
+                shareability: Shareability::Inner,
             },
         },
     ],
 );
 
-/// The physical address space available to the kernel on this board.
+/// The physical address space available to the kernel on this board, which is also the size of
+/// the kernel's virtual address space.
 pub type KernelAddressSpace = AddressSpace<{ END_INCLUSIVE + 1 }>;
 
 /// Gets the virtual memory layout used on this board.
-pub fn virtual_memory_layout() -> &'static KernelVirtualMemoryLayout<3> {
+pub fn virtual_memory_layout() -> &'static KernelVirtualMemoryLayout<5> {
     &LAYOUT
 }
\ No newline at end of file
@@ -6,6 +6,7 @@ pub mod cpu;
 
 pub mod devices;
 pub mod memory;
+pub mod irq;
 pub mod bcm;
 
 #[cfg(feature = "board_raspi3")]
@@ -14,6 +15,9 @@ pub const BOARD_NAME: &str = "Raspberry Pi 3";
 #[cfg(feature = "board_raspi4")]
 pub const BOARD_NAME: &str = "Raspberry Pi 4";
 
+#[cfg(feature = "board_qemu_virt")]
+pub const BOARD_NAME: &str = "QEMU virt";
+
 pub unsafe fn init() -> Result<(), Error> {
     static INIT_COMPLETE: AtomicBool = AtomicBool::new(false);
     if INIT_COMPLETE.load(Ordering::Relaxed) {
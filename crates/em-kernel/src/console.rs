@@ -1,6 +1,6 @@
 use core;
 
-use crate::sync::Mutex;
+use crate::sync::OnceCell;
 
 pub trait Write {
     /// Write a single character.
@@ -51,14 +51,21 @@ impl Read for NullConsole {
 }
 impl Console for NullConsole {}
 
-static CURRENT_CONSOLE: Mutex<&'static (dyn Console + Sync)> = Mutex::new(&NULL_CONSOLE);
+static CURRENT_CONSOLE: OnceCell<&'static (dyn Console + Sync)> = OnceCell::new();
 
+/// Registers the console. Until then, output is discarded.
+///
+/// # Panics
+///
+/// - If a console was registered before.
 pub fn register_console(new_console: &'static (dyn Console + Sync)) {
-    CURRENT_CONSOLE.lock(|con| *con = new_console);
+    if CURRENT_CONSOLE.set(new_console).is_err() {
+        panic!("Console already registered");
+    }
 }
 
 pub fn console() -> &'static (dyn Console + Sync) {
-    CURRENT_CONSOLE.lock(|con| *con)
+    CURRENT_CONSOLE.get().copied().unwrap_or(&NULL_CONSOLE)
 }
 
 #[doc(hidden)]
@@ -1,18 +1,15 @@
-use crate::{error::Error, sync::Mutex, info};
+use alloc::vec::Vec;
 
-// TODO: Bump allocator and dynamic allocation!
-const MAX_DRIVERS: usize = 5;
+use crate::{error::Error, sync::IrqSafeMutex, info, interrupt::{self, IrqHandler, IrqHandlerDescriptor, IrqNumber}};
 
 struct DriverManagerInner {
-    next_index: usize,
-    descriptors: [Option<DeviceDriverDescriptor>; MAX_DRIVERS],
+    descriptors: Vec<DeviceDriverDescriptor>,
 }
 
 impl DriverManagerInner {
     pub const fn new() -> Self {
         Self {
-            next_index: 0,
-            descriptors: [None; MAX_DRIVERS],
+            descriptors: Vec::new(),
         }
     }
 }
@@ -24,6 +21,11 @@ pub trait DeviceDriver {
         // Some drivers don't need to be initialized!
         Ok(())
     }
+
+    /// The handler for the driver's IRQ, if it services one.
+    fn irq_handler(&'static self) -> Option<&'static (dyn IrqHandler + Sync)> {
+        None
+    }
 }
 
 pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), Error>;
@@ -32,39 +34,36 @@ pub type DeviceDriverPostInitCallback = unsafe fn() -> Result<(), Error>;
 pub struct DeviceDriverDescriptor {
     driver: &'static (dyn DeviceDriver + Sync),
     post_init_callback: Option<DeviceDriverPostInitCallback>,
+    irq_number: Option<IrqNumber>,
 }
 
 impl DeviceDriverDescriptor {
     pub const fn new(
         driver: &'static (dyn DeviceDriver + Sync),
         post_init_callback: Option<DeviceDriverPostInitCallback>,
+        irq_number: Option<IrqNumber>,
     ) -> Self {
         Self {
             driver,
             post_init_callback,
+            irq_number,
         }
     }
 }
 
 pub struct DriverManager {
-    inner: Mutex<DriverManagerInner>,
+    inner: IrqSafeMutex<DriverManagerInner>,
 }
 
 impl DriverManager {
     pub const fn new() -> Self {
         Self {
-            inner: Mutex::new(DriverManagerInner::new()),
+            inner: IrqSafeMutex::new(DriverManagerInner::new()),
         }
     }
 
     pub fn install(&self, descriptor: DeviceDriverDescriptor) {
-        self.inner.lock(|inner| {
-            if inner.next_index >= MAX_DRIVERS {
-                panic!("Too many drivers installed!");
-            }
-            inner.descriptors[inner.next_index] = Some(descriptor);
-            inner.next_index += 1;
-        })
+        self.inner.lock(|inner| inner.descriptors.push(descriptor))
     }
 
     pub unsafe fn initialize(&self) {
@@ -91,16 +90,39 @@ impl DriverManager {
         self.for_each_descriptor(|descriptor| {
             info!("Initialized driver '{}'", descriptor.driver.name())
         });
+
+        // The interrupt controller is itself a driver, so IRQs can only be wired up once all
+        // drivers are running.
+        self.for_each_descriptor(|descriptor| {
+            if let Some(irq_number) = descriptor.irq_number {
+                if let Err(x) = Self::enable_irq(descriptor, irq_number) {
+                    panic!(
+                        "Failed to enable IRQ {} for driver '{}': {}",
+                        irq_number,
+                        descriptor.driver.name(),
+                        x);
+                }
+            }
+        });
+    }
+
+    fn enable_irq(descriptor: &DeviceDriverDescriptor, irq_number: IrqNumber) -> Result<(), Error> {
+        let handler = descriptor
+            .driver
+            .irq_handler()
+            .ok_or("Driver declares an IRQ but has no handler")?;
+
+        let controller = interrupt::controller();
+        controller.register_handler(IrqHandlerDescriptor::new(
+            irq_number,
+            descriptor.driver.name(),
+            handler,
+        ))?;
+        controller.enable(irq_number)
     }
 
-    fn for_each_descriptor<'a>(&'a self, f: impl FnMut(&'a DeviceDriverDescriptor)) {
-        self.inner.lock(|inner| {
-            inner
-                .descriptors
-                .iter()
-                .filter_map(|x| x.as_ref())
-                .for_each(f)
-        })
+    fn for_each_descriptor(&self, f: impl FnMut(&DeviceDriverDescriptor)) {
+        self.inner.lock(|inner| inner.descriptors.iter().for_each(f))
     }
 }
 
@@ -29,6 +29,7 @@ impl core::fmt::Display for PrivilegeKind {
 }
 
 /// Represents the current privilege level of the processor.
+#[derive(Copy, Clone)]
 pub struct PrivilegeLevel {
     kind: PrivilegeKind,
     name: &'static str,
@@ -52,4 +53,120 @@ impl PrivilegeLevel {
     pub fn kind(&self) -> PrivilegeKind {
         self.kind
     }
+}
+
+/// What a core was executing when it took an interrupt.
+#[derive(Copy, Clone)]
+pub struct InterruptedState {
+    /// The address of the instruction the core resumes at once the interrupt is handled.
+    pub pc: usize,
+
+    /// The privilege level the core was executing at.
+    pub privilege_level: PrivilegeLevel,
+}
+
+/// The kind of memory access that caused a fault.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum AccessType {
+    /// A data read.
+    Read,
+
+    /// A data write.
+    Write,
+
+    /// An instruction fetch.
+    Execute,
+}
+
+impl core::fmt::Display for AccessType {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+            Self::Execute => write!(f, "execute"),
+        }
+    }
+}
+
+/// An architecture-independent classification of why a memory access faulted.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub enum FaultKind {
+    /// The address was outside the range supported by the translation regime.
+    AddressSize,
+
+    /// No valid translation exists for the address.
+    Translation,
+
+    /// The translation exists, but has not been marked as accessed.
+    AccessFlag,
+
+    /// The translation exists, but does not permit this kind of access.
+    Permission,
+
+    /// The address was not suitably aligned for the access.
+    Alignment,
+
+    /// The memory system reported an error for the access, or for the translation table walk.
+    External,
+
+    /// Multiple translations matched the address.
+    TlbConflict,
+
+    /// The memory type does not support exclusive or atomic accesses.
+    UnsupportedAtomic,
+
+    /// A fault the kernel does not know how to classify.
+    Unknown,
+}
+
+impl core::fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            Self::AddressSize => write!(f, "address size fault"),
+            Self::Translation => write!(f, "translation fault"),
+            Self::AccessFlag => write!(f, "access flag fault"),
+            Self::Permission => write!(f, "permission fault"),
+            Self::Alignment => write!(f, "alignment fault"),
+            Self::External => write!(f, "external abort"),
+            Self::TlbConflict => write!(f, "TLB conflict"),
+            Self::UnsupportedAtomic => write!(f, "unsupported atomic access"),
+            Self::Unknown => write!(f, "unknown fault"),
+        }
+    }
+}
+
+/// An architecture-independent description of a faulting memory access.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub struct Fault {
+    /// Why the access faulted.
+    pub kind: FaultKind,
+
+    /// The kind of access that faulted.
+    pub access: AccessType,
+
+    /// The faulting virtual address, if the hardware reported it.
+    pub address: Option<usize>,
+
+    /// The translation table level at which the fault was detected, if applicable.
+    pub level: Option<u8>,
+}
+
+impl core::fmt::Display for Fault {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self.access {
+            AccessType::Execute => write!(f, "Instruction abort")?,
+            _ => write!(f, "Data abort")?,
+        }
+
+        match self.address {
+            Some(address) => write!(f, " at FAR={:#018x}", address)?,
+            None => write!(f, " at FAR=<unknown>")?,
+        }
+
+        write!(f, " ({}", self.kind)?;
+        if let Some(level) = self.level {
+            write!(f, " level {}", level)?;
+        }
+        write!(f, ", {})", self.access)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,221 @@
+use core::{cell::Cell, fmt, sync::atomic::{AtomicUsize, Ordering}};
+
+use crate::{arch, error::Error, exception::InterruptedState, sync::{OnceCell, RwLock}, info, percpu};
+
+/// An interrupt request line, numbered as understood by the registered interrupt controller.
+#[derive(Eq, PartialEq, Debug, Copy, Clone)]
+pub struct IrqNumber(pub usize);
+
+impl fmt::Display for IrqNumber {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Implemented by anything that services an interrupt.
+pub trait IrqHandler {
+    /// Called from interrupt context when the associated IRQ fires.
+    fn handle(&self) -> Result<(), Error>;
+}
+
+/// Associates an IRQ with the handler that services it.
+#[derive(Copy, Clone)]
+pub struct IrqHandlerDescriptor {
+    number: IrqNumber,
+    name: &'static str,
+    handler: &'static (dyn IrqHandler + Sync),
+}
+
+impl IrqHandlerDescriptor {
+    pub const fn new(
+        number: IrqNumber,
+        name: &'static str,
+        handler: &'static (dyn IrqHandler + Sync),
+    ) -> Self {
+        Self {
+            number,
+            name,
+            handler,
+        }
+    }
+
+    /// The IRQ this handler services.
+    pub fn number(&self) -> IrqNumber {
+        self.number
+    }
+}
+
+pub trait InterruptController {
+    /// Registers the handler for the IRQ identified by the descriptor.
+    ///
+    /// The IRQ is not enabled by registering a handler for it.
+    fn register_handler(&self, descriptor: IrqHandlerDescriptor) -> Result<(), Error>;
+
+    /// Enables delivery of an IRQ.
+    fn enable(&self, irq: IrqNumber) -> Result<(), Error>;
+
+    /// Services all pending IRQs. Called from the IRQ exception vector.
+    fn dispatch(&self);
+
+    /// Prepares the controller for taking IRQs on a secondary core.
+    ///
+    /// Per-core IRQs that were enabled on the boot core are enabled on the executing core as well.
+    fn init_secondary_core(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Raises the inter-processor interrupt on a core.
+    fn send_ipi(&self, _core: usize) -> Result<(), Error> {
+        Err("IPIs are not supported".into())
+    }
+
+    /// Print the registered handlers.
+    fn print_handlers(&self) {}
+}
+
+/// A fixed-size table of IRQ handlers, indexed by IRQ number.
+///
+/// Used by interrupt controller drivers to store their registered handlers.
+pub struct IrqHandlerTable<const NUM_IRQS: usize> {
+    inner: RwLock<[Option<IrqHandlerDescriptor>; NUM_IRQS]>,
+}
+
+impl<const NUM_IRQS: usize> IrqHandlerTable<NUM_IRQS> {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: RwLock::new([None; NUM_IRQS]),
+        }
+    }
+
+    /// Stores the handler for the IRQ identified by the descriptor.
+    pub fn insert(&self, descriptor: IrqHandlerDescriptor) -> Result<(), Error> {
+        let number = descriptor.number().0;
+        if number >= NUM_IRQS {
+            return Err("IRQ number out of range".into());
+        }
+
+        self.inner.write(|table| {
+            if table[number].is_some() {
+                return Err("IRQ handler already registered".into());
+            }
+
+            table[number] = Some(descriptor);
+            Ok(())
+        })
+    }
+
+    /// Runs the handler registered for an IRQ.
+    ///
+    /// # Panics
+    ///
+    /// - If no handler is registered for the IRQ, or the handler fails.
+    pub fn handle(&self, irq: IrqNumber) {
+        let descriptor = self.inner.read(|table| table.get(irq.0).copied().flatten());
+
+        match descriptor {
+            None => panic!("No handler registered for IRQ {}", irq),
+            Some(descriptor) => {
+                if let Err(x) = descriptor.handler.handle() {
+                    panic!("Error handling IRQ {} ('{}'): {}", irq, descriptor.name, x);
+                }
+            }
+        }
+    }
+
+    /// Print the registered handlers.
+    pub fn print(&self) {
+        self.inner.read(|table| {
+            for descriptor in table.iter().filter_map(|x| x.as_ref()) {
+                info!("      {: >3}. {}", descriptor.number, descriptor.name);
+            }
+        })
+    }
+}
+
+struct NullInterruptController;
+static NULL_INTERRUPT_CONTROLLER: NullInterruptController = NullInterruptController;
+
+impl InterruptController for NullInterruptController {
+    fn register_handler(&self, _descriptor: IrqHandlerDescriptor) -> Result<(), Error> {
+        Err("No interrupt controller registered".into())
+    }
+
+    fn enable(&self, _irq: IrqNumber) -> Result<(), Error> {
+        Err("No interrupt controller registered".into())
+    }
+
+    fn dispatch(&self) {}
+}
+
+static CURRENT_INTERRUPT_CONTROLLER: OnceCell<&'static (dyn InterruptController + Sync)> =
+    OnceCell::new();
+
+/// Registers the interrupt controller.
+///
+/// # Panics
+///
+/// - If an interrupt controller was registered before.
+pub fn register_controller(new_controller: &'static (dyn InterruptController + Sync)) {
+    if CURRENT_INTERRUPT_CONTROLLER.set(new_controller).is_err() {
+        panic!("Interrupt controller already registered");
+    }
+}
+
+pub fn controller() -> &'static (dyn InterruptController + Sync) {
+    CURRENT_INTERRUPT_CONTROLLER
+        .get()
+        .copied()
+        .unwrap_or(&NULL_INTERRUPT_CONTROLLER)
+}
+
+percpu! {
+    /// How many IRQ exceptions the core is currently handling.
+    static IRQ_DEPTH: AtomicUsize = AtomicUsize::new(0);
+}
+
+percpu! {
+    /// How many IRQ exceptions the core has handled.
+    static IRQ_COUNT: AtomicUsize = AtomicUsize::new(0);
+}
+
+percpu! {
+    /// What the core was executing when it took the IRQ it is handling.
+    static INTERRUPTED_STATE: Cell<Option<InterruptedState>> = Cell::new(None);
+}
+
+/// Services pending IRQs. Called by the architecture's IRQ exception vector.
+pub fn dispatch(interrupted: InterruptedState) {
+    IRQ_DEPTH.get().fetch_add(1, Ordering::Relaxed);
+    IRQ_COUNT.get().fetch_add(1, Ordering::Relaxed);
+    let outer = INTERRUPTED_STATE.get().replace(Some(interrupted));
+
+    controller().dispatch();
+
+    INTERRUPTED_STATE.get().set(outer);
+    IRQ_DEPTH.get().fetch_sub(1, Ordering::Relaxed);
+}
+
+/// What the executing core was doing before it took the IRQ it is handling, if any.
+pub fn interrupted_state() -> Option<InterruptedState> {
+    INTERRUPTED_STATE.get().get()
+}
+
+/// Whether the executing core is handling an IRQ.
+pub fn in_irq_context() -> bool {
+    IRQ_DEPTH.get().load(Ordering::Relaxed) > 0
+}
+
+/// How many IRQ exceptions a core has handled.
+pub fn irq_count(core: usize) -> usize {
+    IRQ_COUNT.for_core(core).load(Ordering::Relaxed)
+}
+
+/// Unmask IRQs on the executing core.
+///
+/// # Safety
+///
+/// - Handlers may run as soon as this returns, so everything they touch must be initialized.
+pub unsafe fn local_irq_unmask() {
+    arch::interrupt::local_irq_unmask();
+}
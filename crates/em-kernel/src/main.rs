@@ -4,14 +4,17 @@
 #![feature(const_option)]
 #![feature(unchecked_math)]
 #![feature(core_intrinsics)]
+#![feature(alloc_error_handler)]
 #![no_main]
 #![no_std]
 
+extern crate alloc;
+
 use core::time::Duration;
 
 use memory::MemoryManagementUnit;
 
-use crate::{exception::PrivilegeLevel, console::Write};
+use crate::exception::PrivilegeLevel;
 
 mod panic;
 mod arch;
@@ -22,8 +25,13 @@ mod error;
 mod driver;
 mod time;
 mod exception;
+mod interrupt;
 mod memory;
+mod smp;
 mod utils;
+mod percpu;
+mod task;
+mod self_test;
 
 /// Kernel Entry Point.
 ///
@@ -31,46 +39,134 @@ mod utils;
 ///
 /// - Only a single core must be active and running this function.
 unsafe fn kenter() -> ! {
+    // Install the exception vectors first, so faults during early boot are reported.
+    arch::exception::handling_init();
+
+    percpu::init();
+
+    // The kernel translation tables are allocated from it.
+    if let Err(e) = memory::frame::init() {
+        panic!("Failed to initialize the frame allocator: {}", e);
+    }
+
     info!("Initializing MMU");
 
     if let Err(e) = arch::memory::mmu().enable() {
         panic!("Failed to enable MMU: {}", e);
     }
 
+    // Memory that is both writable and executable would let any memory corruption inject code.
+    if let Some(addr) = arch::memory::mmu().find_writable_executable() {
+        panic!("Memory at {:#018x} is both writable and executable", addr.0);
+    }
+
+    if let Err(e) = memory::stack::init() {
+        panic!("Failed to set up the kernel stacks: {}", e);
+    }
+
     // Initialize the board, which will attach devices to the device manager
     board::init().expect("failed to initialize board");
 
     // Start drivers
     driver::manager().initialize();
 
+    if let Err(e) = smp::init() {
+        panic!("Failed to initialize SMP: {}", e);
+    }
+
+    // Drivers have registered their IRQ handlers, so it's safe to take interrupts now.
+    interrupt::local_irq_unmask();
+
+    // The kernel is fully initialized, so the other cores can join in.
+    smp::start_secondary_cores();
+
     // Jump to safe code
     kmain()
 }
 
+/// Kernel Entry Point of the secondary cores.
+///
+/// # Safety
+///
+/// - Must only be entered once per core, after the boot core has initialized the kernel.
+unsafe fn ksecondary() -> ! {
+    arch::exception::handling_init();
+
+    if let Err(e) = arch::memory::mmu().enable_secondary() {
+        panic!("Failed to enable MMU on core {}: {}", arch::cpu::core_id(), e);
+    }
+
+    // The template is only read once the MMU is on, so it is coherent with the boot core's caches.
+    percpu::init();
+
+    if let Err(e) = interrupt::controller().init_secondary_core() {
+        panic!("Failed to initialize interrupts on core {}: {}", arch::cpu::core_id(), e);
+    }
+
+    interrupt::local_irq_unmask();
+
+    smp::secondary_core_online();
+    info!("Core {} online", arch::cpu::core_id());
+
+    task::set_current("idle");
+
+    // There is no work for the secondary cores yet, apart from cross-core calls.
+    loop {
+        arch::cpu::wait_for_interrupt();
+    }
+}
+
 fn kmain() -> ! {
+    task::set_current("kmain");
+
     info!(
         "Emily version {}",
         env!("CARGO_PKG_VERSION")
     );
     info!("Board: {}", board::BOARD_NAME);
 
-    info!("MMU online. Special regions:");
+    info!("MMU online with {} KiB pages. Special regions:", arch::memory::mmu().page_size() / 1024);
     board::memory::virtual_memory_layout().print_layout();
 
+    info!("Kernel translation tables:");
+    board::memory::virtual_memory_layout().dump_translation();
+
+    info!("Physical memory ({} KiB frames):", memory::frame::FRAME_SIZE / 1024);
+    memory::frame::print_stats();
+
+    info!("Kernel heap:");
+    memory::heap::print_usage();
+
+    info!("Slab caches:");
+    memory::slab::print_stats();
+
+    info!("Kernel stacks:");
+    memory::stack::print_usage();
+
+    info!("Demand-paged memory:");
+    memory::region::print_stats();
+
     let privl = PrivilegeLevel::current();
     info!("Current Privilege Level: {} - {}", privl.kind(), privl.name());
 
+    info!("Cores:");
+    smp::print_cores();
+
     info!("Timer resolution: {}ns", time::keeper().resolution().as_nanos());
 
-    let remapped_uart = unsafe { board::bcm::pl011_uart::PL011Uart::new(0x1FFF_1000) };
-    writeln!(
-        remapped_uart,
-        "[     !!!    ] Writing through the remapped UART at 0x1FFF_1000"
-    )
-    .unwrap();
+    info!("Registered IRQ handlers:");
+    interrupt::controller().print_handlers();
+
+    if cfg!(feature = "self_test") {
+        self_test::run();
+    }
+
+    time::keeper()
+        .set_timeout_once(Duration::from_secs(3), || info!("Timeout expired"))
+        .unwrap();
 
     loop {
-        info!("Sleeping 1 second");
-        time::keeper().spin_for(Duration::from_secs(1));
+        info!("Sleeping 1 second ({} IRQs handled)", interrupt::irq_count(arch::cpu::core_id()));
+        time::keeper().sleep_for(Duration::from_secs(1));
     }
 }
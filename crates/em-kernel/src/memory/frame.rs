@@ -0,0 +1,278 @@
+//! Physical page-frame allocator.
+//!
+//! The board's RAM is tracked in frames of [`FRAME_SIZE`] bytes with a bitmap, where a set bit
+//! marks a frame that is allocated or reserved. Allocations are first-fit, starting the search
+//! after the previous allocation so that the front of RAM isn't rescanned every time.
+
+use core::ops::Range;
+
+use crate::{arch::memory::Granule64KiB, board, error::Error, info, sync::IrqSafeMutex, utils};
+
+use super::PhysicalAddress;
+
+/// The size of a physical frame, in bytes. As large as the largest translation granule, so that a
+/// frame holds whole pages whichever granule the MMU runs with.
+pub const FRAME_SIZE: usize = Granule64KiB::SIZE;
+
+const RAM_START: usize = board::memory::dram::START;
+const MAX_FRAMES: usize = (board::memory::dram::END_EXCLUSIVE - RAM_START) / FRAME_SIZE;
+const BITMAP_WORDS: usize = MAX_FRAMES.div_ceil(u64::BITS as usize);
+
+const _: () = assert!(RAM_START % FRAME_SIZE == 0 && MAX_FRAMES > 0);
+
+/// Usage statistics of the physical memory, in frames.
+#[derive(Copy, Clone)]
+pub struct FrameStats {
+    /// The frames of RAM known to the allocator.
+    pub total: usize,
+
+    /// The frames available for allocation.
+    pub free: usize,
+
+    /// The frames occupied by the kernel image, which are never freed.
+    pub reserved: usize,
+}
+
+impl FrameStats {
+    /// The frames currently handed out by the allocator.
+    pub fn allocated(&self) -> usize {
+        self.total - self.free - self.reserved
+    }
+}
+
+struct FrameAllocator {
+    /// Bit N of word W is set if frame `W * 64 + N` is in use.
+    bitmap: [u64; BITMAP_WORDS],
+
+    /// The frames occupied by the kernel image.
+    reserved: Range<usize>,
+
+    stats: FrameStats,
+
+    /// The frame the next search starts at.
+    next: usize,
+}
+
+impl FrameAllocator {
+    const fn new() -> Self {
+        // Nothing can be allocated until `init` marks the RAM as free.
+        Self {
+            bitmap: [u64::MAX; BITMAP_WORDS],
+            reserved: 0..0,
+            stats: FrameStats {
+                total: 0,
+                free: 0,
+                reserved: 0,
+            },
+            next: 0,
+        }
+    }
+
+    fn init(&mut self, ram: Range<usize>, reserved: Range<usize>) -> Result<(), Error> {
+        if self.stats.total != 0 {
+            return Err("Frame allocator already initialized".into());
+        }
+
+        for frame in ram.clone() {
+            self.set_used(frame, false);
+        }
+
+        // The reserved range may extend past the RAM, but only frames within it count.
+        let reserved = reserved.start.max(ram.start)..reserved.end.min(ram.end);
+        for frame in reserved.clone() {
+            self.set_used(frame, true);
+        }
+
+        self.stats = FrameStats {
+            total: ram.len(),
+            free: ram.len() - reserved.len(),
+            reserved: reserved.len(),
+        };
+        self.reserved = reserved;
+        self.next = self.reserved.end;
+
+        Ok(())
+    }
+
+    fn is_used(&self, frame: usize) -> bool {
+        self.bitmap[frame / 64] & (1 << (frame % 64)) != 0
+    }
+
+    fn set_used(&mut self, frame: usize, used: bool) {
+        if used {
+            self.bitmap[frame / 64] |= 1 << (frame % 64);
+        } else {
+            self.bitmap[frame / 64] &= !(1 << (frame % 64));
+        }
+    }
+
+    /// Finds `count` free frames in a row, starting below `end`.
+    fn find_free_run(&self, start: usize, end: usize, count: usize) -> Option<usize> {
+        let mut run_start = start;
+        let mut frame = start;
+
+        while frame < end {
+            // Skip over fully used words at once.
+            if frame % 64 == 0 && self.bitmap[frame / 64] == u64::MAX {
+                frame += 64;
+                run_start = frame;
+                continue;
+            }
+
+            if self.is_used(frame) {
+                run_start = frame + 1;
+            } else if frame + 1 - run_start == count {
+                return Some(run_start);
+            }
+
+            frame += 1;
+        }
+
+        None
+    }
+
+    /// Allocates `count` frames in a row, all of them below `end`.
+    fn alloc(&mut self, count: usize, end: usize) -> Option<usize> {
+        if count == 0 || count > self.stats.free {
+            return None;
+        }
+
+        // Runs starting before `next` are only found on the second pass.
+        let first = self
+            .find_free_run(self.next.min(end), end, count)
+            .or_else(|| self.find_free_run(0, (self.next + count - 1).min(end), count))?;
+
+        for frame in first..first + count {
+            self.set_used(frame, true);
+        }
+
+        self.stats.free -= count;
+        self.next = first + count;
+
+        Some(first)
+    }
+
+    fn free(&mut self, first: usize, count: usize) {
+        let frames = first..first + count;
+
+        assert!(frames.end <= MAX_FRAMES, "Freeing frames outside of RAM");
+        assert!(
+            frames.end <= self.reserved.start || frames.start >= self.reserved.end,
+            "Freeing frames of the kernel image"
+        );
+
+        for frame in frames {
+            if !self.is_used(frame) {
+                panic!("Freeing frame {:#x}, which is not allocated", frame_address(frame).0);
+            }
+            self.set_used(frame, false);
+        }
+
+        self.stats.free += count;
+    }
+}
+
+static FRAME_ALLOCATOR: IrqSafeMutex<FrameAllocator> = IrqSafeMutex::new(FrameAllocator::new());
+
+fn frame_address(frame: usize) -> PhysicalAddress {
+    PhysicalAddress(RAM_START + frame * FRAME_SIZE)
+}
+
+/// The frames covering `range`, including frames that are only partially covered.
+fn frames_covering(range: Range<usize>) -> Range<usize> {
+    let start = range.start.saturating_sub(RAM_START) / FRAME_SIZE;
+    let end = range.end.saturating_sub(RAM_START).div_ceil(FRAME_SIZE);
+
+    start.min(MAX_FRAMES)..end.min(MAX_FRAMES)
+}
+
+/// Hand the board's RAM to the allocator, apart from the kernel image.
+///
+/// # Safety
+///
+/// - Must be called before any frame is allocated. Everything outside the kernel image is
+///   considered free, so no other code may be using it.
+pub unsafe fn init() -> Result<(), Error> {
+    let ram = frames_covering(RAM_START..board::memory::dram::END_EXCLUSIVE);
+    let reserved = frames_covering(board::memory::kernel_image_range());
+
+    FRAME_ALLOCATOR.lock(|allocator| allocator.init(ram, reserved))
+}
+
+/// Allocate a single frame.
+pub fn alloc() -> Result<PhysicalAddress, Error> {
+    alloc_contiguous(1)
+}
+
+/// Allocate `count` physically contiguous frames, and return the address of the first one.
+pub fn alloc_contiguous(count: usize) -> Result<PhysicalAddress, Error> {
+    FRAME_ALLOCATOR
+        .lock(|allocator| allocator.alloc(count, MAX_FRAMES))
+        .map(frame_address)
+        .ok_or_else(|| "Out of physical memory".into())
+}
+
+/// Allocate a single frame that ends at or below `end`, for memory that is accessed through a
+/// mapping of part of the RAM.
+pub fn alloc_below(end: PhysicalAddress) -> Result<PhysicalAddress, Error> {
+    let end = (end.0.saturating_sub(RAM_START) / FRAME_SIZE).min(MAX_FRAMES);
+
+    FRAME_ALLOCATOR
+        .lock(|allocator| allocator.alloc(1, end))
+        .map(frame_address)
+        .ok_or_else(|| "Out of physical memory".into())
+}
+
+/// Return a frame obtained from [`alloc`].
+///
+/// # Safety
+///
+/// - The frame must no longer be accessed.
+///
+/// # Panics
+///
+/// - If the frame is not allocated, or `addr` is not frame aligned.
+pub unsafe fn free(addr: PhysicalAddress) {
+    free_contiguous(addr, 1)
+}
+
+/// Return `count` frames obtained from [`alloc_contiguous`]. Parts of a range may be returned
+/// separately.
+///
+/// # Safety
+///
+/// - The frames must no longer be accessed.
+///
+/// # Panics
+///
+/// - If any of the frames is not allocated, or `addr` is not frame aligned.
+pub unsafe fn free_contiguous(addr: PhysicalAddress, count: usize) {
+    let offset = addr.0.checked_sub(RAM_START);
+    assert!(
+        offset.is_some_and(|x| x % FRAME_SIZE == 0),
+        "Freeing an address that is not a frame: {:#x}",
+        addr.0
+    );
+
+    let first = offset.unwrap() / FRAME_SIZE;
+    FRAME_ALLOCATOR.lock(|allocator| allocator.free(first, count))
+}
+
+/// The current usage of the physical memory.
+pub fn stats() -> FrameStats {
+    FRAME_ALLOCATOR.lock(|allocator| allocator.stats)
+}
+
+/// Print the usage of the physical memory.
+pub fn print_stats() {
+    let stats = stats();
+    let print = |frames: usize, what: &str| {
+        let (size, unit) = utils::size_human_readable_ceil(frames * FRAME_SIZE);
+        info!("      {: >5} frames | {: >4} {: <3} | {}", frames, size, unit, what);
+    };
+
+    print(stats.total, "Total");
+    print(stats.reserved, "Kernel image");
+    print(stats.allocated(), "Allocated");
+    print(stats.free, "Free");
+}
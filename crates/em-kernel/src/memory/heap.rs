@@ -0,0 +1,266 @@
+//! Kernel heap, backing the `alloc` crate.
+//!
+//! Small allocations are served by the slab caches. For everything else, free memory is kept in a
+//! list of blocks sorted by address, and allocations are first-fit. Freed blocks are merged with
+//! their neighbours. When no block fits, the heap grows by taking frames from the frame
+//! allocator. Frames are never given back.
+
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    mem, ptr,
+};
+
+use crate::{error::Error, info, sync::IrqSafeMutex, utils};
+
+use super::{frame, phys_to_virt, slab};
+
+/// The smallest number of frames the heap grows by, to avoid growing on every other allocation.
+const MIN_GROWTH_FRAMES: usize = 4;
+
+/// A free block. Lives at the start of the block it describes.
+struct FreeBlock {
+    size: usize,
+    next: *mut FreeBlock,
+}
+
+/// Every block is aligned to, and a multiple of, the size of a `FreeBlock`. A free block can
+/// therefore be placed in any leftover space.
+const BLOCK_SIZE: usize = mem::size_of::<FreeBlock>();
+
+const _: () = assert!(BLOCK_SIZE.is_power_of_two() && BLOCK_SIZE >= mem::align_of::<FreeBlock>());
+
+/// Usage statistics of the kernel heap.
+#[derive(Copy, Clone)]
+pub struct HeapStats {
+    /// The bytes taken from the frame allocator.
+    pub size: usize,
+
+    /// The bytes handed out, including the padding to `BLOCK_SIZE`.
+    pub used: usize,
+
+    /// The number of live allocations.
+    pub allocations: usize,
+
+    /// The number of free blocks. Many small blocks indicate fragmentation.
+    pub free_blocks: usize,
+}
+
+struct Heap {
+    /// The first free block, by address.
+    free_list: *mut FreeBlock,
+    stats: HeapStats,
+}
+
+// The free blocks are only accessed with the heap locked.
+unsafe impl Send for Heap {}
+
+impl Heap {
+    const fn new() -> Self {
+        Self {
+            free_list: ptr::null_mut(),
+            stats: HeapStats {
+                size: 0,
+                used: 0,
+                allocations: 0,
+                free_blocks: 0,
+            },
+        }
+    }
+
+    /// The size and alignment of the block that serves `layout`.
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let size = layout.size().max(1).next_multiple_of(BLOCK_SIZE);
+        let align = layout.align().max(BLOCK_SIZE);
+
+        (size, align)
+    }
+
+    fn alloc(&mut self, layout: Layout) -> Result<*mut u8, Error> {
+        let (size, align) = Self::block_layout(layout);
+
+        if let Some(block) = self.take_first_fit(size, align) {
+            return Ok(block);
+        }
+
+        self.grow(size + align)?;
+        self.take_first_fit(size, align).ok_or_else(|| "Heap grown too little".into())
+    }
+
+    /// Carves `size` bytes aligned to `align` out of the first free block that has room for them.
+    fn take_first_fit(&mut self, size: usize, align: usize) -> Option<*mut u8> {
+        let mut link: *mut *mut FreeBlock = &mut self.free_list;
+
+        unsafe {
+            while !(*link).is_null() {
+                let block = *link;
+                let start = block as usize;
+                let end = start + (*block).size;
+                let alloc_start = start.next_multiple_of(align);
+                let alloc_end = alloc_start + size;
+
+                if alloc_end > end {
+                    link = &mut (*block).next;
+                    continue;
+                }
+
+                // Whatever is left on either side is a multiple of `BLOCK_SIZE`, so it can stay in
+                // the list. The front keeps the block's header, so only its size changes.
+                let next = (*block).next;
+                let back = if alloc_end < end {
+                    self.write_block(alloc_end, end - alloc_end, next)
+                } else {
+                    next
+                };
+
+                if alloc_start > start {
+                    (*block).size = alloc_start - start;
+                    (*block).next = back;
+                } else {
+                    *link = back;
+                    self.stats.free_blocks -= 1;
+                }
+
+                self.stats.used += size;
+                self.stats.allocations += 1;
+
+                return Some(alloc_start as *mut u8);
+            }
+        }
+
+        None
+    }
+
+    /// Takes enough frames from the frame allocator to hold at least `bytes` more.
+    fn grow(&mut self, bytes: usize) -> Result<(), Error> {
+        let frames = bytes.div_ceil(frame::FRAME_SIZE).max(MIN_GROWTH_FRAMES);
+        let start = phys_to_virt(frame::alloc_contiguous(frames)?).0;
+        let size = frames * frame::FRAME_SIZE;
+
+        self.stats.size += size;
+        unsafe { self.insert_free(start, size) };
+
+        Ok(())
+    }
+
+    /// Returns a block to the free list, merging it with adjacent free blocks.
+    ///
+    /// # Safety
+    ///
+    /// - The block must be unused memory of the heap, and not be in the free list already.
+    unsafe fn insert_free(&mut self, start: usize, size: usize) {
+        let end = start + size;
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut next = self.free_list;
+
+        while !next.is_null() && (next as usize) < start {
+            prev = next;
+            next = (*next).next;
+        }
+
+        debug_assert!(
+            (prev.is_null() || prev as usize + (*prev).size <= start)
+                && (next.is_null() || next as usize >= end),
+            "Heap block freed twice"
+        );
+
+        let merges_next = !next.is_null() && next as usize == end;
+        let merges_prev = !prev.is_null() && prev as usize + (*prev).size == start;
+
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                (*prev).size += size + (*next).size;
+                (*prev).next = (*next).next;
+                self.stats.free_blocks -= 1;
+            }
+            (true, false) => (*prev).size += size,
+            (false, merges_next) => {
+                let block = if merges_next {
+                    self.stats.free_blocks -= 1;
+                    self.write_block(start, size + (*next).size, (*next).next)
+                } else {
+                    self.write_block(start, size, next)
+                };
+
+                if prev.is_null() {
+                    self.free_list = block;
+                } else {
+                    (*prev).next = block;
+                }
+            }
+        }
+    }
+
+    /// Creates a free block, which the caller links into the list.
+    fn write_block(&mut self, start: usize, size: usize, next: *mut FreeBlock) -> *mut FreeBlock {
+        let block = start as *mut FreeBlock;
+        unsafe { block.write(FreeBlock { size, next }) };
+        self.stats.free_blocks += 1;
+
+        block
+    }
+
+    /// # Safety
+    ///
+    /// - `ptr` must have been returned by `alloc` for the same `layout`, and not be freed yet.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+
+        self.stats.used -= size;
+        self.stats.allocations -= 1;
+        self.insert_free(ptr as usize, size);
+    }
+}
+
+/// The allocator behind `Box`, `Vec` and the other `alloc` types.
+struct KernelHeap {
+    inner: IrqSafeMutex<Heap>,
+}
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let result = match slab::cache_for(layout) {
+            Some(cache) => cache.alloc(),
+            None => self.inner.lock(|heap| heap.alloc(layout)),
+        };
+
+        result.unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        match slab::cache_for(layout) {
+            Some(cache) => cache.free(ptr),
+            None => self.inner.lock(|heap| heap.dealloc(ptr, layout)),
+        }
+    }
+}
+
+#[global_allocator]
+static KERNEL_HEAP: KernelHeap = KernelHeap {
+    inner: IrqSafeMutex::new(Heap::new()),
+};
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    panic!(
+        "Kernel heap exhausted: failed to allocate {} bytes aligned to {}",
+        layout.size(),
+        layout.align()
+    );
+}
+
+/// The current usage of the kernel heap.
+pub fn stats() -> HeapStats {
+    KERNEL_HEAP.inner.lock(|heap| heap.stats)
+}
+
+/// Print the usage of the kernel heap.
+pub fn print_usage() {
+    let stats = stats();
+    let (size, size_unit) = utils::size_human_readable_ceil(stats.size);
+    let (used, used_unit) = utils::size_human_readable_ceil(stats.used);
+
+    info!(
+        "      {} {} used of {} {} | {} allocations | {} free blocks",
+        used, used_unit, size, size_unit, stats.allocations, stats.free_blocks
+    );
+}
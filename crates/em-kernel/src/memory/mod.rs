@@ -1,10 +1,86 @@
 use core::{ops::RangeInclusive, fmt};
 
-use crate::utils;
-
+use crate::{arch, board, error::Error, sync::IrqSafeMutex, utils};
+
+pub mod frame;
+pub mod heap;
+pub mod region;
+pub mod slab;
+pub mod stack;
+
+/// The attributes of device mappings created by [`ioremap`].
+const IOREMAP_ATTRIBUTES: MemoryAttributes = MemoryAttributes {
+    memory_type: MemoryType::Device,
+    access: MemoryAccess::ReadWrite,
+    executable: false,
+    user_accessible: false,
+    shareability: Shareability::Outer,
+};
+
+/// The start of the kernel's address space, in the upper range translated through TTBR1.
+///
+/// Physical memory is linearly mapped there: the kernel accesses physical address `x` at
+/// `KERNEL_VIRT_START + x`. The lower range is left for the address spaces of user processes.
+///
+/// Must match `__kernel_virt_start_addr` in the board's linker script.
+pub const KERNEL_VIRT_START: usize = 0usize.wrapping_sub(board::memory::KernelAddressSpace::SIZE);
+
+/// The start of the unused part of the board's I/O remapping window.
+static IOREMAP_NEXT: IrqSafeMutex<usize> = IrqSafeMutex::new(board::memory::ioremap::START);
+
+#[derive(Copy, Clone)]
 pub struct PhysicalAddress(pub usize);
+
+#[derive(Copy, Clone)]
 pub struct VirtualAddress(pub usize);
 
+/// Map `len` bytes of device registers at `phys` into the kernel's address space, and return
+/// their virtual address.
+///
+/// The mapping is carved from the board's I/O remapping window, and never removed.
+pub fn ioremap(phys: PhysicalAddress, len: usize) -> Result<VirtualAddress, Error> {
+    let page_size = arch::memory::mmu().page_size();
+    let offset = phys.0 % page_size;
+    let map_len = (offset + len).next_multiple_of(page_size);
+
+    IOREMAP_NEXT.lock(|next| {
+        let virt = *next;
+        if map_len > board::memory::ioremap::END_INCLUSIVE - virt + 1 {
+            return Err("I/O remapping window exhausted".into());
+        }
+
+        unsafe {
+            arch::memory::mmu().map(
+                VirtualAddress(virt),
+                PhysicalAddress(phys.0 - offset),
+                map_len,
+                &IOREMAP_ATTRIBUTES,
+            )?
+        };
+
+        *next += map_len;
+        Ok(VirtualAddress(virt + offset))
+    })
+}
+
+/// The address at which the kernel accesses a physical address in RAM.
+///
+/// RAM is accessed through the linear mapping at [`KERNEL_VIRT_START`].
+pub fn phys_to_virt(addr: PhysicalAddress) -> VirtualAddress {
+    VirtualAddress(KERNEL_VIRT_START + addr.0)
+}
+
+/// The physical address of a virtual address in the kernel's linear mapping, such as the address
+/// of a static or of memory from the kernel heap.
+///
+/// Not valid for mappings created at runtime, such as those of [`ioremap`] or demand-paged
+/// memory. [`MemoryManagementUnit::virt_to_phys`] translates those.
+pub fn linear_virt_to_phys(addr: VirtualAddress) -> PhysicalAddress {
+    debug_assert!(addr.0 >= KERNEL_VIRT_START, "Not a kernel address: {:#x}", addr.0);
+
+    PhysicalAddress(addr.0 - KERNEL_VIRT_START)
+}
+
 #[derive(Debug)]
 pub enum EnableError {
     AlreadyEnabled,
@@ -23,14 +99,138 @@ impl fmt::Display for EnableError {
 }
 
 pub trait MemoryManagementUnit {
-    /// Called during kernel initialization to initialize the Memory Management Unit
+    /// Called during kernel initialization to set up the kernel translation tables from the
+    /// board's layout, and switch to them from the mapping the boot code entered the kernel with.
     /// 
     /// # Safety
     /// Changes hardware global state and should only be called once, and at the appropriate time.
     unsafe fn enable(&self) -> Result<(), EnableError>;
 
+    /// Called on each secondary core to switch its MMU to the translation tables set up by
+    /// [`MemoryManagementUnit::enable`].
+    ///
+    /// # Safety
+    /// Changes hardware state of the executing core, and must only be called once the boot core
+    /// has set up the tables.
+    unsafe fn enable_secondary(&self) -> Result<(), EnableError>;
+
     /// Indicates if the MMU is enabled
     fn is_enabled(&self) -> bool;
+
+    /// The size of a page, which depends on the translation granule the MMU runs with.
+    fn page_size(&self) -> usize;
+
+    /// Maps `len` bytes at `virt` to `phys`, replacing any existing mapping. Both addresses and
+    /// `len` must be page aligned.
+    ///
+    /// # Safety
+    /// Changes the translation of live memory. Nothing may access the range through an existing
+    /// mapping while it changes, which rules out the kernel's code, data and stacks. Where the
+    /// range is part of a block, the same goes for the whole block, which is split into pages.
+    unsafe fn map(
+        &self,
+        virt: VirtualAddress,
+        phys: PhysicalAddress,
+        len: usize,
+        attributes: &MemoryAttributes,
+    ) -> Result<(), Error>;
+
+    /// Removes the mapping of `len` bytes at `virt`, so that accesses fault.
+    ///
+    /// # Safety
+    /// Same as [`MemoryManagementUnit::map`].
+    unsafe fn unmap(&self, virt: VirtualAddress, len: usize) -> Result<(), Error>;
+
+    /// Changes the attributes of the mapped range of `len` bytes at `virt`. Fails without changing
+    /// anything if part of the range is not mapped.
+    ///
+    /// # Safety
+    /// Same as [`MemoryManagementUnit::map`].
+    unsafe fn protect(&self, virt: VirtualAddress, len: usize, attributes: &MemoryAttributes) -> Result<(), Error>;
+
+    /// Counts the blocks and pages that currently translate `virt_range`.
+    fn count_mappings(&self, virt_range: RangeInclusive<usize>) -> MappingCounts;
+
+    /// Looks up how `virt` is translated, by walking the kernel translation tables in software.
+    fn translate(&self, virt: VirtualAddress) -> Option<Mapping>;
+
+    /// The physical address `virt` translates to, for example to hand a buffer to a device for
+    /// DMA.
+    fn virt_to_phys(&self, virt: VirtualAddress) -> Option<PhysicalAddress> {
+        self.translate(virt).map(|x| x.phys)
+    }
+
+    /// Asks the MMU itself how `virt` is translated, as the executing core sees it. Unlike
+    /// [`MemoryManagementUnit::translate`], the result includes stale TLB entries.
+    fn translate_hw(&self, virt: VirtualAddress) -> Option<HwTranslation>;
+
+    /// Calls `f` for every page and block of the kernel translation tables, in address order, with
+    /// its start address. `f` runs with the tables locked, so it should be quick, and must not
+    /// change mappings.
+    fn for_each_mapping(&self, f: &mut dyn FnMut(VirtualAddress, &Mapping));
+
+    /// Walks the translation tables for memory that is mapped both writable and executable, and
+    /// returns the first such address.
+    fn find_writable_executable(&self) -> Option<VirtualAddress>;
+}
+
+/// The translation of a virtual address, by a page or a block.
+#[derive(Clone)]
+pub struct Mapping {
+    /// The physical address the virtual address translates to.
+    pub phys: PhysicalAddress,
+
+    pub attributes: MemoryAttributes,
+
+    /// The size of the page or block.
+    pub size: usize,
+}
+
+/// The translation of a virtual address as the MMU reports it, which leaves out the access
+/// permissions.
+#[derive(Clone)]
+pub struct HwTranslation {
+    /// The physical address the virtual address translates to.
+    pub phys: PhysicalAddress,
+
+    /// The type of the memory, or `None` if the MMU reports attributes the kernel doesn't use.
+    pub memory_type: Option<MemoryType>,
+
+    pub shareability: Shareability,
+}
+
+impl HwTranslation {
+    /// Whether the MMU applies the memory type and shareability of `attributes`.
+    pub fn agrees_with(&self, attributes: &MemoryAttributes) -> bool {
+        // Device and non-cacheable memory is always reported as outer shareable.
+        let shareability = match attributes.memory_type {
+            MemoryType::Normal | MemoryType::NormalWriteThrough => attributes.shareability.clone(),
+            MemoryType::NormalNonCacheable | MemoryType::Device | MemoryType::DeviceGre => {
+                Shareability::Outer
+            }
+        };
+
+        self.memory_type.as_ref() == Some(&attributes.memory_type) && self.shareability == shareability
+    }
+}
+
+/// The descriptors translating a range of virtual addresses.
+#[derive(Copy, Clone)]
+pub struct MappingCounts {
+    /// Descriptors translating more than a page at once.
+    pub blocks: usize,
+
+    pub pages: usize,
+}
+
+impl fmt::Display for MappingCounts {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.blocks == 0 && self.pages == 0 {
+            return write!(f, "unmapped");
+        }
+
+        write!(f, "{} blocks, {} pages", self.blocks, self.pages)
+    }
 }
 
 /// Describes the characteristics of a translation granule.
@@ -72,25 +272,36 @@ impl<const AS_SIZE: usize> AddressSpace<AS_SIZE> {
 /// Translation types
 #[derive(Clone)]
 pub enum Translation {
-    /// Virtual Addresses map directly to Physical Addresses
-    Identity,
+    /// Virtual Addresses map to Physical Addresses through the kernel's linear mapping, see
+    /// [`phys_to_virt`]
+    Linear,
 
-    /// Virtual Addresses are offset by the provided amount to get the Physical Address
-    Offset(usize),
+    /// Virtual Addresses are not mapped, and accesses fault, until mappings are added at runtime
+    Unmapped,
 }
 
 /// Identifies the type of a region of memory.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum MemoryType {
     /// The memory is standard RAM, eligible for storing arbitrary data and code.
     Normal,
 
+    /// RAM that bypasses the caches, for buffers shared with devices that don't snoop them.
+    NormalNonCacheable,
+
+    /// RAM whose writes go through the caches to memory right away, while reads are cached.
+    NormalWriteThrough,
+
     /// The memory is mapped to a device, and is used for communicating with the device.
-    Device
+    Device,
+
+    /// Device memory whose accesses may be gathered, reordered and acknowledged early, like a
+    /// framebuffer.
+    DeviceGre,
 }
 
 /// Identifies the access permissions of a region of memory.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub enum MemoryAccess {
     /// The region can only be read.
     ReadOnly,
@@ -99,8 +310,22 @@ pub enum MemoryAccess {
     ReadWrite,
 }
 
+/// Identifies which cores and other observers the accesses to a region of memory are coherent
+/// with.
+#[derive(Clone, PartialEq, Eq)]
+pub enum Shareability {
+    /// Only the accessing core.
+    Non,
+
+    /// The cores of the same cluster.
+    Inner,
+
+    /// All observers, including devices.
+    Outer,
+}
+
 /// The attributes associated with a memory region.
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Eq)]
 pub struct MemoryAttributes {
     /// The type of memory identified by this region.
     pub memory_type: MemoryType,
@@ -108,8 +333,15 @@ pub struct MemoryAttributes {
     /// The access permissions of this region.
     pub access: MemoryAccess,
 
-    /// If set, memory in this region can be executed.
+    /// If set, memory in this region can be executed: by user space if it can access the region,
+    /// and by the kernel otherwise.
     pub executable: bool,
+
+    /// If set, user space can access this region with the same permissions as the kernel.
+    pub user_accessible: bool,
+
+    /// The shareability of this region.
+    pub shareability: Shareability,
 }
 
 impl Default for MemoryAttributes {
@@ -118,10 +350,45 @@ impl Default for MemoryAttributes {
             memory_type: MemoryType::Normal,
             access: MemoryAccess::ReadWrite,
             executable: false,
+            user_accessible: false,
+            shareability: Shareability::Inner,
         }
     }
 }
 
+impl fmt::Display for MemoryAttributes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let attr = match self.memory_type {
+            MemoryType::Normal => "N",
+            MemoryType::NormalNonCacheable => "NC",
+            MemoryType::NormalWriteThrough => "WT",
+            MemoryType::Device => "D",
+            MemoryType::DeviceGre => "GRE",
+        };
+
+        let acc_p = match self.access {
+            MemoryAccess::ReadOnly => "RO",
+            MemoryAccess::ReadWrite => "RW",
+        };
+
+        // Memory user space can access is only executable by user space, other memory by the kernel.
+        let xn = match (self.user_accessible, self.executable) {
+            (false, true) => "PX",
+            (false, false) => "PXN",
+            (true, true) => "UX",
+            (true, false) => "UXN",
+        };
+
+        let sh = match self.shareability {
+            Shareability::Non => "NSH",
+            Shareability::Inner => "ISH",
+            Shareability::Outer => "OSH",
+        };
+
+        write!(f, "{: <3} {} {: <3} {}", attr, acc_p, xn, sh)
+    }
+}
+
 /// Describes the virtual address translation of a region of memory.
 pub struct TranslationDescriptor {
     /// The name of the region
@@ -147,26 +414,10 @@ impl fmt::Display for TranslationDescriptor {
 
         let (size, unit) = utils::size_human_readable_ceil(size);
 
-        let attr = match self.attributes.memory_type {
-            MemoryType::Normal => "N",
-            MemoryType::Device => "D",
-        };
-
-        let acc_p = match self.attributes.access {
-            MemoryAccess::ReadOnly => "RO",
-            MemoryAccess::ReadWrite => "RW",
-        };
-
-        let xn = if self.attributes.executable {
-            "PX"
-        } else {
-            "PXN"
-        };
-
         write!(
             f,
-            "      {:#010x} - {:#010x} | {: >3} {} | {: <3} {} {: <3} | {}",
-            start, end, size, unit, attr, acc_p, xn, self.name
+            "      {:#018x} - {:#018x} | {: >3} {} | {} | {}",
+            start, end, size, unit, self.attributes, self.name
         )
     }
 }
@@ -192,36 +443,125 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualMemoryLayout<{ NUM_SPECIAL_RA
     /// For a virtual address, find and return the physical output address and corresponding
     /// attributes.
     ///
-    /// If the address is not found in `inner`, return an identity mapped default with normal
-    /// cacheable DRAM attributes.
+    /// If the address is not found in `inner`, return a linearly mapped default with normal
+    /// cacheable DRAM attributes. Returns `None` for addresses that are not mapped.
     pub fn virt_addr_properties(
         &self,
         virt_addr: usize,
-    ) -> Result<(PhysicalAddress, MemoryAttributes), &'static str> {
-        if virt_addr > self.max_virtual_address {
+    ) -> Result<Option<(PhysicalAddress, MemoryAttributes)>, &'static str> {
+        if virt_addr < KERNEL_VIRT_START || virt_addr > self.max_virtual_address {
             return Err("Address out of range");
         }
 
         for i in self.inner.iter() {
             if (i.virtual_range)().contains(&virt_addr) {
                 let output_addr = match i.translation {
-                    Translation::Identity => virt_addr,
-                    Translation::Offset(a) => a + (virt_addr - (i.virtual_range)().start()),
+                    Translation::Linear => linear_virt_to_phys(VirtualAddress(virt_addr)).0,
+                    Translation::Unmapped => return Ok(None),
                 };
 
-                return Ok((PhysicalAddress(output_addr), i.attributes.clone()));
+                return Ok(Some((PhysicalAddress(output_addr), i.attributes.clone())));
             }
         }
 
-        Ok((PhysicalAddress(virt_addr), MemoryAttributes::default()))
+        Ok(Some((linear_virt_to_phys(VirtualAddress(virt_addr)), MemoryAttributes::default())))
+    }
+
+    /// Whether the `size` bytes from `virt_addr` on all translate alike, so that a single
+    /// descriptor can map them. That is the case unless a region starts or ends within them.
+    pub fn is_uniform(&self, virt_addr: usize, size: usize) -> bool {
+        let last = virt_addr + (size - 1);
+
+        self.inner.iter().all(|i| {
+            let range = (i.virtual_range)();
+            let overlaps = *range.start() <= last && *range.end() >= virt_addr;
+            let contains = *range.start() <= virt_addr && *range.end() >= last;
+
+            !overlaps || contains
+        })
+    }
+
+    /// Print what the kernel translation tables map, with pages and blocks that translate alike
+    /// coalesced into ranges. Each range is checked against the layout, and against the MMU's own
+    /// view of its start address and memory attributes.
+    ///
+    /// The ranges are collected first and printed once the translation tables are unlocked, as
+    /// other cores may need them meanwhile.
+    pub fn dump_translation(&self) {
+        use alloc::vec::Vec;
+
+        use crate::info;
+
+        struct Run {
+            virt: RangeInclusive<usize>,
+            mapping: Mapping,
+            origin: &'static str,
+        }
+
+        let print = |run: &Run| {
+            let (size, unit) = utils::size_human_readable_ceil(run.virt.end() - run.virt.start() + 1);
+            let hw = arch::memory::mmu().translate_hw(VirtualAddress(*run.virt.start()));
+            let hw_check = match hw {
+                Some(x) if x.phys.0 == run.mapping.phys.0 && x.agrees_with(&run.mapping.attributes) => "",
+                _ => " | MMU disagrees",
+            };
+
+            info!(
+                "      {:#018x} - {:#018x} | {:#011x} | {: >3} {} | {} | {}{}",
+                run.virt.start(),
+                run.virt.end(),
+                run.mapping.phys.0,
+                size,
+                unit,
+                run.mapping.attributes,
+                run.origin,
+                hw_check
+            );
+        };
+
+        let mut runs: Vec<Run> = Vec::new();
+        let mut visit = |virt: VirtualAddress, mapping: &Mapping| {
+            // Mappings that the layout leaves to runtime are expected to differ from it.
+            let origin = match self.virt_addr_properties(virt.0) {
+                Ok(Some((phys, attributes))) if phys.0 == mapping.phys.0 && attributes == mapping.attributes => {
+                    "layout"
+                }
+                Ok(None) => "runtime",
+                _ => "DIFFERS FROM LAYOUT",
+            };
+
+            // The ranges are inclusive, as the last one may end at the top of the address space.
+            if let Some(x) = runs.last_mut() {
+                let len = x.virt.end() - x.virt.start() + 1;
+
+                if x.virt.end().wrapping_add(1) == virt.0
+                    && x.mapping.phys.0 + len == mapping.phys.0
+                    && x.mapping.attributes == mapping.attributes
+                    && x.origin == origin
+                {
+                    x.virt = *x.virt.start()..=x.virt.end() + mapping.size;
+                    return;
+                }
+            }
+
+            runs.push(Run {
+                virt: virt.0..=virt.0 + (mapping.size - 1),
+                mapping: mapping.clone(),
+                origin,
+            });
+        };
+
+        arch::memory::mmu().for_each_mapping(&mut visit);
+        runs.iter().for_each(print);
     }
 
-    /// Print the memory layout.
+    /// Print the memory layout, along with the descriptors that translate each region.
     pub fn print_layout(&self) {
         use crate::info;
 
         for i in self.inner.iter() {
-            info!("{}", i);
+            let counts = arch::memory::mmu().count_mappings((i.virtual_range)());
+            info!("{} | {}", i, counts);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,192 @@
+//! Lazily populated regions of virtual memory.
+//!
+//! An address space keeps a list of the regions it reserved. A region takes no physical memory
+//! until it is touched: the access faults, and the page-fault handler backs the chunk around the
+//! faulting address with a fresh frame. Such faults are counted as minor faults.
+//!
+//! Only the kernel's address space exists so far, whose regions are carved from the board's
+//! demand-paged window. Regions are never released.
+
+use alloc::vec::Vec;
+use core::{ops::Range, ptr};
+
+use crate::{
+    arch, board,
+    error::Error,
+    exception::{AccessType, Fault, FaultKind},
+    info,
+    sync::IrqSafeMutex,
+    utils,
+};
+
+use super::{frame, phys_to_virt, MemoryAttributes, MemoryManagementUnit, VirtualAddress};
+
+/// What backs the pages of a region once they are touched.
+#[derive(Copy, Clone)]
+pub enum Backing {
+    /// Anonymous memory, zeroed on first touch.
+    Zero,
+}
+
+/// A reserved range of virtual memory.
+pub struct Region {
+    /// The addresses of the region, which are frame aligned.
+    pub range: Range<usize>,
+
+    pub backing: Backing,
+    pub attributes: MemoryAttributes,
+}
+
+/// Usage statistics of an address space's regions.
+#[derive(Copy, Clone)]
+pub struct RegionStats {
+    /// The bytes reserved by the regions.
+    pub reserved: usize,
+
+    /// The bytes backed by frames so far.
+    pub populated: usize,
+
+    /// The faults that populated a region.
+    pub minor_faults: usize,
+}
+
+/// The regions of an address space, sorted by address.
+pub struct RegionList {
+    regions: Vec<Region>,
+
+    /// The addresses the regions are carved from.
+    window: Range<usize>,
+
+    /// The start of the unused part of `window`.
+    next: usize,
+
+    stats: RegionStats,
+}
+
+impl RegionList {
+    /// Create an instance, whose regions are carved from `window`.
+    pub const fn new(window: Range<usize>) -> Self {
+        Self {
+            regions: Vec::new(),
+            next: window.start,
+            window,
+            stats: RegionStats {
+                reserved: 0,
+                populated: 0,
+                minor_faults: 0,
+            },
+        }
+    }
+
+    /// Reserves a region of at least `len` bytes.
+    fn reserve(&mut self, len: usize, backing: Backing, attributes: MemoryAttributes) -> Result<VirtualAddress, Error> {
+        let len = len.max(1).next_multiple_of(frame::FRAME_SIZE);
+        if len > self.window.end - self.next {
+            return Err("Demand-paged window exhausted".into());
+        }
+
+        let start = self.next;
+        self.regions.push(Region {
+            range: start..start + len,
+            backing,
+            attributes,
+        });
+
+        self.next += len;
+        self.stats.reserved += len;
+
+        Ok(VirtualAddress(start))
+    }
+
+    /// The region containing `addr`.
+    fn find(&self, addr: usize) -> Option<&Region> {
+        let index = self.regions.partition_point(|x| x.range.end <= addr);
+
+        self.regions.get(index).filter(|x| x.range.contains(&addr))
+    }
+
+    /// Backs the chunk of a region around `addr` with a frame, if `access` is allowed there.
+    /// Returns whether the fault was handled, and the access can be retried.
+    fn populate(&mut self, addr: usize, access: AccessType) -> bool {
+        let Some(region) = self.find(addr) else {
+            return false;
+        };
+
+        if access == AccessType::Execute && !region.attributes.executable {
+            return false;
+        }
+
+        // Frames can hold several pages, so the whole frame is mapped at once.
+        let chunk = addr & !(frame::FRAME_SIZE - 1);
+        let mmu = arch::memory::mmu();
+
+        // Another core may have populated the chunk since the access faulted.
+        let counts = mmu.count_mappings(chunk..=chunk);
+        if counts.blocks != 0 || counts.pages != 0 {
+            return true;
+        }
+
+        let frame = match frame::alloc() {
+            Ok(x) => x,
+            Err(e) => panic!("Failed to populate demand-paged memory at {:#018x}: {}", addr, e),
+        };
+
+        match region.backing {
+            Backing::Zero => unsafe { ptr::write_bytes(phys_to_virt(frame).0 as *mut u8, 0, frame::FRAME_SIZE) },
+        }
+
+        // Nothing accesses the chunk through another mapping, as it has none.
+        if let Err(e) = unsafe { mmu.map(VirtualAddress(chunk), frame, frame::FRAME_SIZE, &region.attributes) } {
+            panic!("Failed to populate demand-paged memory at {:#018x}: {}", addr, e);
+        }
+
+        self.stats.populated += frame::FRAME_SIZE;
+        self.stats.minor_faults += 1;
+
+        true
+    }
+}
+
+/// The regions of the kernel's address space.
+static KERNEL_REGIONS: IrqSafeMutex<RegionList> = IrqSafeMutex::new(RegionList::new(
+    board::memory::demand_paged::START..board::memory::demand_paged::END_INCLUSIVE + 1,
+));
+
+/// Reserve `len` bytes of zeroed kernel memory, which only take up physical memory once touched.
+pub fn reserve_zeroed(len: usize) -> Result<VirtualAddress, Error> {
+    KERNEL_REGIONS.lock(|regions| regions.reserve(len, Backing::Zero, MemoryAttributes::default()))
+}
+
+/// Handles a fault of the kernel, which populates its regions. Returns whether the fault was
+/// handled, and the access can be retried.
+///
+/// Code touching the regions must not hold the locks of the frame allocator and the translation
+/// tables, which the handler takes.
+pub fn handle_fault(fault: &Fault) -> bool {
+    let Some(addr) = fault.address else {
+        return false;
+    };
+
+    if fault.kind != FaultKind::Translation {
+        return false;
+    }
+
+    KERNEL_REGIONS.lock(|regions| regions.populate(addr, fault.access))
+}
+
+/// The current usage of the kernel's regions.
+pub fn stats() -> RegionStats {
+    KERNEL_REGIONS.lock(|regions| regions.stats)
+}
+
+/// Print the usage of the kernel's regions.
+pub fn print_stats() {
+    let stats = stats();
+    let (populated, populated_unit) = utils::size_human_readable_ceil(stats.populated);
+    let (reserved, reserved_unit) = utils::size_human_readable_ceil(stats.reserved);
+
+    info!(
+        "      {} {} populated of {} {} | {} minor faults",
+        populated, populated_unit, reserved, reserved_unit, stats.minor_faults
+    );
+}
@@ -0,0 +1,276 @@
+//! Slab allocator for small, fixed-size objects.
+//!
+//! Each size class has a cache, which carves frames into objects of that size. Freed objects go
+//! to a magazine of the executing core first, so that the hot path never contends with the other
+//! cores. Only when a magazine runs empty or full does it exchange objects with the cache's
+//! depot, which is shared by all cores.
+//!
+//! Slabs are never returned to the frame allocator.
+
+use core::{
+    alloc::Layout,
+    mem,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use crate::{error::Error, info, percpu, sync::IrqSafeMutex};
+
+use super::{frame, phys_to_virt};
+
+/// The number of objects a magazine holds when full.
+const MAGAZINE_SIZE: usize = 16;
+
+/// The number of objects moved between a magazine and the depot at once.
+const MAGAZINE_BATCH: usize = MAGAZINE_SIZE / 2;
+
+/// The byte freed objects are filled with in debug builds, to catch use after free.
+#[cfg(debug_assertions)]
+const POISON: u8 = 0x6B;
+
+/// A free object in the depot. Lives at the start of the object.
+struct FreeObject {
+    next: *mut FreeObject,
+}
+
+/// The objects of a cache in the depot.
+struct Depot {
+    free: *mut FreeObject,
+}
+
+// The free objects are only accessed with the depot locked.
+unsafe impl Send for Depot {}
+
+/// Free objects of a cache, private to a core.
+#[derive(Copy, Clone)]
+struct Magazine {
+    rounds: usize,
+    objects: [*mut u8; MAGAZINE_SIZE],
+}
+
+// A magazine is only accessed by the core it belongs to.
+unsafe impl Send for Magazine {}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            rounds: 0,
+            objects: [ptr::null_mut(); MAGAZINE_SIZE],
+        }
+    }
+
+    fn push(&mut self, object: *mut u8) {
+        self.objects[self.rounds] = object;
+        self.rounds += 1;
+    }
+
+    fn pop(&mut self) -> *mut u8 {
+        self.rounds -= 1;
+        self.objects[self.rounds]
+    }
+}
+
+struct CacheCounters {
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    slabs: AtomicUsize,
+
+    /// How often a magazine was refilled from the depot.
+    refills: AtomicUsize,
+
+    /// How often a magazine was flushed to the depot.
+    flushes: AtomicUsize,
+}
+
+/// The cache of a size class.
+pub struct SlabCache {
+    /// The index into `CACHES` and the per-CPU magazines.
+    index: usize,
+    object_size: usize,
+    depot: IrqSafeMutex<Depot>,
+    counters: CacheCounters,
+}
+
+impl SlabCache {
+    const fn new(index: usize, object_size: usize) -> Self {
+        assert!(object_size.is_power_of_two() && object_size >= mem::size_of::<FreeObject>());
+
+        Self {
+            index,
+            object_size,
+            depot: IrqSafeMutex::new(Depot {
+                free: ptr::null_mut(),
+            }),
+            counters: CacheCounters {
+                allocs: AtomicUsize::new(0),
+                frees: AtomicUsize::new(0),
+                slabs: AtomicUsize::new(0),
+                refills: AtomicUsize::new(0),
+                flushes: AtomicUsize::new(0),
+            },
+        }
+    }
+
+    /// Allocate an object.
+    pub fn alloc(&self) -> Result<*mut u8, Error> {
+        let object = MAGAZINES.get().lock(|magazines| {
+            let magazine = &mut magazines[self.index];
+            if magazine.rounds == 0 {
+                self.refill(magazine)?;
+            }
+
+            Ok::<_, Error>(magazine.pop())
+        })?;
+
+        #[cfg(debug_assertions)]
+        self.check_poison(object);
+
+        self.counters.allocs.fetch_add(1, Ordering::Relaxed);
+        Ok(object)
+    }
+
+    /// Return an object obtained from [`SlabCache::alloc`].
+    ///
+    /// # Safety
+    ///
+    /// - The object must have been allocated from this cache, and must no longer be accessed.
+    pub unsafe fn free(&self, object: *mut u8) {
+        #[cfg(debug_assertions)]
+        ptr::write_bytes(object, POISON, self.object_size);
+
+        MAGAZINES.get().lock(|magazines| {
+            let magazine = &mut magazines[self.index];
+            if magazine.rounds == MAGAZINE_SIZE {
+                self.flush(magazine);
+            }
+
+            magazine.push(object);
+        });
+
+        self.counters.frees.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Moves a batch of objects from the depot into an empty magazine.
+    fn refill(&self, magazine: &mut Magazine) -> Result<(), Error> {
+        self.depot.lock(|depot| {
+            if depot.free.is_null() {
+                self.grow(depot)?;
+            }
+
+            while magazine.rounds < MAGAZINE_BATCH && !depot.free.is_null() {
+                let object = depot.free;
+                depot.free = unsafe { (*object).next };
+
+                // Objects in a magazine are poisoned in full, but the depot's link overwrote the
+                // start of this one.
+                #[cfg(debug_assertions)]
+                unsafe {
+                    ptr::write_bytes(object as *mut u8, POISON, mem::size_of::<FreeObject>())
+                };
+
+                magazine.push(object as *mut u8);
+            }
+
+            Ok::<_, Error>(())
+        })?;
+
+        self.counters.refills.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Moves a batch of objects from a full magazine to the depot.
+    fn flush(&self, magazine: &mut Magazine) {
+        self.depot.lock(|depot| {
+            for _ in 0..MAGAZINE_BATCH {
+                let object = magazine.pop() as *mut FreeObject;
+                unsafe { object.write(FreeObject { next: depot.free }) };
+                depot.free = object;
+            }
+        });
+
+        self.counters.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Carves a new frame into objects, and puts them into the depot.
+    fn grow(&self, depot: &mut Depot) -> Result<(), Error> {
+        let start = phys_to_virt(frame::alloc()?).0;
+
+        for object in (start..start + frame::FRAME_SIZE).step_by(self.object_size).rev() {
+            let object = object as *mut FreeObject;
+
+            unsafe {
+                #[cfg(debug_assertions)]
+                ptr::write_bytes(object as *mut u8, POISON, self.object_size);
+
+                object.write(FreeObject { next: depot.free });
+            }
+
+            depot.free = object;
+        }
+
+        self.counters.slabs.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Panics if a free object was written to.
+    #[cfg(debug_assertions)]
+    fn check_poison(&self, object: *mut u8) {
+        let bytes = unsafe { core::slice::from_raw_parts(object, self.object_size) };
+
+        if let Some(offset) = bytes.iter().position(|x| *x != POISON) {
+            panic!(
+                "Slab object {:#x} ({} bytes) modified after free at offset {}",
+                object as usize, self.object_size, offset
+            );
+        }
+    }
+
+    fn print_stats(&self) {
+        let allocs = self.counters.allocs.load(Ordering::Relaxed);
+        let frees = self.counters.frees.load(Ordering::Relaxed);
+
+        info!(
+            "      {: >4} B | {: >3} slabs | {: >6} in use | {: >8} allocs | {: >8} frees | {: >6} refills | {: >6} flushes",
+            self.object_size,
+            self.counters.slabs.load(Ordering::Relaxed),
+            allocs.saturating_sub(frees),
+            allocs,
+            frees,
+            self.counters.refills.load(Ordering::Relaxed),
+            self.counters.flushes.load(Ordering::Relaxed),
+        );
+    }
+}
+
+const NUM_CACHES: usize = 8;
+
+static CACHES: [SlabCache; NUM_CACHES] = [
+    SlabCache::new(0, 16),
+    SlabCache::new(1, 32),
+    SlabCache::new(2, 64),
+    SlabCache::new(3, 128),
+    SlabCache::new(4, 256),
+    SlabCache::new(5, 512),
+    SlabCache::new(6, 1024),
+    SlabCache::new(7, 2048),
+];
+
+percpu! {
+    /// The magazines of the executing core, one per cache.
+    static MAGAZINES: IrqSafeMutex<[Magazine; NUM_CACHES]> =
+        IrqSafeMutex::new([Magazine::new(); NUM_CACHES]);
+}
+
+/// The cache serving objects of `layout`, if it is small enough for any.
+pub fn cache_for(layout: Layout) -> Option<&'static SlabCache> {
+    let size = layout.size().max(layout.align());
+
+    CACHES.iter().find(|x| x.object_size >= size)
+}
+
+/// Print the counters of every cache.
+pub fn print_stats() {
+    for cache in CACHES.iter() {
+        cache.print_stats();
+    }
+}
@@ -0,0 +1,98 @@
+//! Kernel stacks.
+//!
+//! Every core's stack has an unmapped guard below it, so that an overflow faults rather than
+//! corrupting the memory below. The unused part of each stack is painted with a pattern, and the
+//! deepest word no longer holding it marks how much of the stack was ever used.
+
+use core::ops::Range;
+
+use crate::{arch, board, error::Error, info, utils};
+
+use super::{MemoryManagementUnit, VirtualAddress};
+
+/// The size of the guard below each stack.
+const GUARD_SIZE: usize = board::cpu::STACK_GUARD_SIZE;
+
+/// The pattern unused stack memory is painted with.
+const PAINT: u64 = 0x57AC_57AC_57AC_57AC;
+
+/// The bytes below the current stack pointer that are left unpainted on the boot core, for the
+/// painting code itself.
+const PAINT_MARGIN: usize = 1024;
+
+/// The guard below the stack of `core`.
+fn guard(core: usize) -> Range<usize> {
+    let start = board::memory::kernel_stack(core).start;
+
+    start - GUARD_SIZE..start
+}
+
+/// Paints a range of stack memory.
+///
+/// # Safety
+///
+/// - The range must be unused stack memory.
+unsafe fn paint(range: Range<usize>) {
+    for word in range.step_by(8) {
+        (word as *mut u64).write_volatile(PAINT);
+    }
+}
+
+/// Unmaps the guards of all stacks, and paints the unused stack memory.
+///
+/// # Safety
+///
+/// - Must be called on the boot core, once the kernel translation tables are in use and before
+///   the secondary cores are started.
+pub unsafe fn init() -> Result<(), Error> {
+    let boot_core = board::cpu::BOOT_CORE_ID as usize;
+    let mmu = arch::memory::mmu();
+
+    for core in 0..board::cpu::NUM_CORES {
+        let guard = guard(core);
+        mmu.unmap(VirtualAddress(guard.start), guard.len())?;
+
+        let stack = board::memory::kernel_stack(core);
+        if core != boot_core {
+            paint(stack);
+            continue;
+        }
+
+        // The boot core is running on its stack, so only the part below the stack pointer is
+        // unused.
+        let sp = arch::cpu::stack_pointer();
+        paint(stack.start..(sp - PAINT_MARGIN) & !7);
+    }
+
+    Ok(())
+}
+
+/// The core whose stack guard contains `addr`. An access there means the stack overflowed.
+pub fn guard_owner(addr: usize) -> Option<usize> {
+    (0..board::cpu::NUM_CORES).find(|core| guard(*core).contains(&addr))
+}
+
+/// The most bytes the stack of `core` has held at once.
+pub fn high_water_mark(core: usize) -> usize {
+    let stack = board::memory::kernel_stack(core);
+
+    // Stacks grow down, so the lowest word that was written marks the deepest use.
+    let deepest = stack
+        .clone()
+        .step_by(8)
+        .find(|word| unsafe { (*word as *const u64).read_volatile() } != PAINT)
+        .unwrap_or(stack.end);
+
+    stack.end - deepest
+}
+
+/// Print the high-water mark of every core's stack.
+pub fn print_usage() {
+    for core in 0..board::cpu::NUM_CORES {
+        let stack = board::memory::kernel_stack(core);
+        let (used, used_unit) = utils::size_human_readable_ceil(high_water_mark(core));
+        let (size, size_unit) = utils::size_human_readable_ceil(stack.len());
+
+        info!("      Core {} | {: >3} {} used of {} {}", core, used, used_unit, size, size_unit);
+    }
+}
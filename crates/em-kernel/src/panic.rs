@@ -1,37 +1,120 @@
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
 
-use crate::{arch, println};
+use crate::{arch, interrupt, memory::MemoryManagementUnit, println, smp, sync, task, time};
 
-fn panic_prevent_reenter() {
-    use core::sync::atomic::{AtomicBool, Ordering};
+/// How long the panicking core waits for the other cores to report their state.
+const HALT_TIMEOUT: Duration = Duration::from_millis(100);
 
+const NO_CORE: usize = usize::MAX;
+
+/// The core that handles the panic.
+static PANICKING_CORE: AtomicUsize = AtomicUsize::new(NO_CORE);
+
+/// The number of other cores that stopped since the panic started.
+static HALTED_CORES: AtomicUsize = AtomicUsize::new(0);
+
+/// Claims the panic for the executing core, or returns the core that claimed it before.
+fn claim_panic(core: usize) -> Result<(), usize> {
     #[cfg(not(target_arch = "aarch64"))]
     compile_error!("Add the target_arch to above's check if the following code is safe to use");
 
-    static PANIC_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+    // Exclusive accesses don't work with the MMU off, but then the boot core is the only one
+    // running anyway.
+    if !arch::memory::mmu().is_enabled() {
+        return match PANICKING_CORE.load(Ordering::Relaxed) {
+            NO_CORE => {
+                PANICKING_CORE.store(core, Ordering::Relaxed);
+                Ok(())
+            }
+            x => Err(x),
+        };
+    }
 
-    if !PANIC_IN_PROGRESS.load(Ordering::Relaxed) {
-        PANIC_IN_PROGRESS.store(true, Ordering::Relaxed);
+    PANICKING_CORE
+        .compare_exchange(NO_CORE, core, Ordering::AcqRel, Ordering::Acquire)
+        .map(|_| ())
+}
 
-        return;
+/// Runs on the other cores when a core panics. Reports what the core was doing, and stops it.
+fn halt_on_panic() {
+    let core = arch::cpu::core_id();
+
+    match interrupt::interrupted_state() {
+        Some(state) => println!(
+            "  Core {}: halted at PC {:#018x} ({}), task '{}'",
+            core,
+            state.pc,
+            state.privilege_level.name(),
+            task::current()
+        ),
+        None => println!("  Core {}: halted, task '{}'", core, task::current()),
     }
 
+    HALTED_CORES.fetch_add(1, Ordering::Release);
     arch::cpu::halt();
 }
 
+/// Stops all other online cores, and waits for them to report their state.
+fn halt_other_cores() {
+    let signaled = smp::call_on_others_nowait(halt_on_panic);
+    if signaled == 0 {
+        return;
+    }
+
+    println!("Halting {} other cores:", signaled);
+
+    let deadline = time::keeper().uptime() + HALT_TIMEOUT;
+    sync::spin_while(|| {
+        HALTED_CORES.load(Ordering::Acquire) < signaled && time::keeper().uptime() < deadline
+    });
+
+    let halted = HALTED_CORES.load(Ordering::Acquire);
+    if halted < signaled {
+        println!("  {} cores did not respond", signaled - halted);
+    }
+}
+
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    // Protect against panic infinite loops if any of the following code panics itself.
-    panic_prevent_reenter();
+    // IRQs would only interfere with reporting the panic.
+    arch::interrupt::local_irq_mask();
 
+    let core = arch::cpu::core_id();
     let (location, line, column) = match info.location() {
         Some(loc) => (loc.file(), loc.line(), loc.column()),
         _ => ("???", 0, 0),
     };
+    let message = info.message().unwrap_or(&format_args!(""));
+
+    match claim_panic(core) {
+        Ok(()) => {}
+
+        // Protect against panic infinite loops if any of the following code panics itself.
+        Err(x) if x == core => arch::cpu::halt(),
+
+        // Report a panic on another core, but leave the rest to the core that panicked first.
+        Err(x) => {
+            println!(
+                "PICNIC AT '{}:{}:{}' ON CORE {}, WHILE CORE {} IS PANICKING",
+                location, line, column, core, x
+            );
+            println!("  {}", message);
+
+            HALTED_CORES.fetch_add(1, Ordering::Release);
+            arch::cpu::halt();
+        }
+    }
 
     println!("PICNIC AT '{}:{}:{}'", location, line, column);
-    println!("  {}", info.message().unwrap_or(&format_args!("")));
+    println!("  {}", message);
+    println!("  Core {}, task '{}'", core, task::current());
+
+    halt_other_cores();
 
     arch::cpu::halt();
-}
\ No newline at end of file
+}
@@ -0,0 +1,97 @@
+//! Per-CPU variables.
+//!
+//! Per-CPU variables are declared with [`percpu!`](crate::percpu!), which places their initial
+//! values in the `.percpu` section. At boot, every core gets its own copy of that section, and the
+//! offset from the section to the copy is kept in a per-core register by the architecture.
+
+use core::{cell::UnsafeCell, ptr::addr_of};
+
+use crate::{arch, board};
+
+// Symbols from the linker script.
+extern "Rust" {
+    static __percpu_start: UnsafeCell<()>;
+    static __percpu_end_exclusive: UnsafeCell<()>;
+    static __percpu_areas_start: UnsafeCell<()>;
+}
+
+/// A variable of which every core has its own copy.
+///
+/// Declare instances with [`percpu!`](crate::percpu!).
+#[repr(transparent)]
+pub struct PerCpu<T> {
+    template: T,
+}
+
+// Each core only accesses its own copy, unless it asks for another one with `for_core`, which
+// requires the copies to be `Sync`.
+unsafe impl<T> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(template: T) -> Self {
+        Self { template }
+    }
+
+    /// The executing core's copy of the variable.
+    #[inline(always)]
+    pub fn get(&self) -> &T {
+        unsafe { self.at_offset(arch::percpu::offset()) }
+    }
+
+    /// The copy of the variable that belongs to `core`.
+    ///
+    /// The copy is concurrently used by `core`, so `T` must be safe to share between cores.
+    pub fn for_core(&self, core: usize) -> &T
+    where
+        T: Sync,
+    {
+        assert!(core < board::cpu::NUM_CORES);
+        unsafe { self.at_offset(area_offset(core)) }
+    }
+
+    unsafe fn at_offset(&self, offset: usize) -> &T {
+        &*((addr_of!(self.template) as usize + offset) as *const T)
+    }
+}
+
+/// Declare a per-CPU variable.
+#[macro_export]
+macro_rules! percpu {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;) => {
+        $(#[$attr])*
+        #[link_section = ".percpu"]
+        $vis static $name: $crate::percpu::PerCpu<$ty> = $crate::percpu::PerCpu::new($init);
+    };
+}
+
+fn template_start() -> usize {
+    unsafe { __percpu_start.get() as usize }
+}
+
+fn template_size() -> usize {
+    unsafe { __percpu_end_exclusive.get() as usize - __percpu_start.get() as usize }
+}
+
+/// The offset from the template to the per-CPU area of `core`.
+fn area_offset(core: usize) -> usize {
+    let area_start = unsafe { __percpu_areas_start.get() as usize } + core * template_size();
+    area_start - template_start()
+}
+
+/// Set up the per-CPU area of the executing core.
+///
+/// # Safety
+///
+/// - Must be called once per core, before any per-CPU variable is accessed.
+pub unsafe fn init() {
+    let offset = area_offset(arch::cpu::core_id());
+
+    core::ptr::copy_nonoverlapping(
+        template_start() as *const u8,
+        (template_start() + offset) as *mut u8,
+        template_size(),
+    );
+
+    arch::percpu::set_offset(offset);
+}
@@ -0,0 +1,73 @@
+//! Boot-time checks of the memory management code, which exercise it the way drivers will.
+//!
+//! Only run when the kernel is built with the `self_test` feature.
+
+use crate::{
+    arch, info,
+    memory::{self, frame, region, MemoryAccess, MemoryAttributes, MemoryManagementUnit, VirtualAddress},
+};
+
+/// The size of the demand-paged buffer the checks work in.
+const BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Runs all checks, and panics if one of them fails.
+pub fn run() {
+    info!("Running self-tests:");
+
+    contiguous_frames();
+
+    let buffer = region::reserve_zeroed(BUFFER_SIZE).unwrap();
+    demand_paging(buffer);
+
+    // The second half of the buffer is never touched, so it has no mapping that would get in the
+    // way.
+    aliasing(VirtualAddress(buffer.0 + BUFFER_SIZE / 2));
+
+    info!("      All self-tests passed");
+}
+
+/// Allocates and frees a run of contiguous frames.
+fn contiguous_frames() {
+    let frames = frame::alloc_contiguous(4).unwrap();
+    info!("      Allocated 4 contiguous frames at {:#x}", frames.0);
+    unsafe { frame::free_contiguous(frames, 4) };
+}
+
+/// Only the parts of a demand-paged buffer that are touched take up physical memory.
+fn demand_paging(buffer: VirtualAddress) {
+    let before = region::stats();
+    unsafe {
+        (buffer.0 as *mut u64).write_volatile(1);
+        ((buffer.0 + BUFFER_SIZE / 4) as *mut u64).write_volatile(2);
+    }
+    assert_eq!(region::stats().minor_faults - before.minor_faults, 2);
+
+    let phys = arch::memory::mmu().virt_to_phys(buffer).unwrap();
+    info!("      Demand-paged buffer at {:#018x} starts in frame {:#x}", buffer.0, phys.0);
+}
+
+/// Maps a frame a second time at `alias`, and checks that both mappings reach the same memory.
+fn aliasing(alias: VirtualAddress) {
+    let mmu = arch::memory::mmu();
+    let frame = frame::alloc().unwrap();
+    let read_only = MemoryAttributes {
+        access: MemoryAccess::ReadOnly,
+        ..Default::default()
+    };
+
+    unsafe {
+        mmu.map(alias, frame, frame::FRAME_SIZE, &Default::default()).unwrap();
+        (alias.0 as *mut u64).write_volatile(0xE5);
+
+        mmu.protect(alias, frame::FRAME_SIZE, &read_only).unwrap();
+        assert!(mmu.translate(alias).unwrap().attributes.access == MemoryAccess::ReadOnly);
+        assert_eq!((memory::phys_to_virt(frame).0 as *const u64).read_volatile(), 0xE5);
+
+        mmu.unmap(alias, frame::FRAME_SIZE).unwrap();
+        assert!(mmu.translate(alias).is_none());
+
+        frame::free(frame);
+    }
+
+    info!("      Aliased frame {:#x} at {:#018x}", frame.0, alias.0);
+}
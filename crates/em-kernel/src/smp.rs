@@ -0,0 +1,240 @@
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    arch, board,
+    error::Error,
+    exception::PrivilegeLevel,
+    info,
+    interrupt::{self, IrqHandler, IrqHandlerDescriptor},
+    percpu,
+    sync::{self, IrqSafeMutex},
+    task, time, warn,
+};
+
+/// How long to wait for a released core to report that it is online.
+const STARTUP_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// The maximum number of cross-core calls that can be queued for a core.
+const MAX_PENDING_CALLS: usize = 4;
+
+/// The cores running the kernel, where bit N is core N.
+static ONLINE_CORES: AtomicUsize = AtomicUsize::new(0);
+
+/// A function queued for execution on another core.
+#[derive(Copy, Clone)]
+struct CrossCall {
+    func: fn(),
+
+    /// Incremented once `func` returned. Lives on the stack of the caller, which waits for it.
+    completion: Option<*const AtomicUsize>,
+}
+
+// The caller keeps `completion` alive until the call is complete.
+unsafe impl Send for CrossCall {}
+
+percpu! {
+    /// The cross-core calls queued for the core.
+    static PENDING_CALLS: IrqSafeMutex<[Option<CrossCall>; MAX_PENDING_CALLS]> =
+        IrqSafeMutex::new([None; MAX_PENDING_CALLS]);
+}
+
+/// Runs the cross-core calls queued for the executing core.
+struct IpiHandler;
+static IPI_HANDLER: IpiHandler = IpiHandler;
+
+impl IrqHandler for IpiHandler {
+    fn handle(&self) -> Result<(), Error> {
+        let next_call = || PENDING_CALLS.get().lock(|calls| calls.iter_mut().find_map(|x| x.take()));
+
+        while let Some(call) = next_call() {
+            (call.func)();
+
+            if let Some(completion) = call.completion {
+                unsafe { (*completion).fetch_add(1, Ordering::Release) };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Register the IPI handler, and mark the boot core as online.
+///
+/// # Safety
+///
+/// - Must be called once, by the boot core, after the interrupt controller is registered.
+pub unsafe fn init() -> Result<(), Error> {
+    let controller = interrupt::controller();
+    controller.register_handler(IrqHandlerDescriptor::new(board::irq::IPI, "IPI", &IPI_HANDLER))?;
+    controller.enable(board::irq::IPI)?;
+
+    ONLINE_CORES.fetch_or(1 << arch::cpu::core_id(), Ordering::Release);
+    Ok(())
+}
+
+/// Release the secondary cores, and wait for each of them to come online.
+///
+/// # Safety
+///
+/// - Must be called once, by the boot core, after the kernel is initialized.
+pub unsafe fn start_secondary_cores() {
+    let boot_core = board::cpu::BOOT_CORE_ID as usize;
+
+    for core in (0..board::cpu::NUM_CORES).filter(|x| *x != boot_core) {
+        if let Err(x) = arch::smp::release_core(core) {
+            warn!("Core {} could not be released: {}", core, x);
+            continue;
+        }
+
+        let deadline = time::keeper().uptime() + STARTUP_TIMEOUT;
+        sync::spin_while(|| !is_online(core) && time::keeper().uptime() < deadline);
+
+        if !is_online(core) {
+            warn!("Core {} did not come online", core);
+        }
+    }
+
+    info!("{} cores online", cores_online());
+}
+
+/// Called by each secondary core once it is ready to run kernel code and take IPIs.
+pub fn secondary_core_online() {
+    ONLINE_CORES.fetch_or(1 << arch::cpu::core_id(), Ordering::Release);
+}
+
+/// Whether a core is running the kernel.
+pub fn is_online(core: usize) -> bool {
+    ONLINE_CORES.load(Ordering::Acquire) & (1 << core) != 0
+}
+
+/// The number of cores running the kernel.
+pub fn cores_online() -> usize {
+    ONLINE_CORES.load(Ordering::Acquire).count_ones() as usize
+}
+
+/// Run `func` on a core, and wait for it to return.
+///
+/// `func` runs in IRQ context, unless `core` is the executing core.
+///
+/// # Panics
+///
+/// - In debug builds, if IRQs are masked: the target could be waiting for this core as well.
+pub fn call_on(core: usize, func: fn()) -> Result<(), Error> {
+    if core == arch::cpu::core_id() {
+        func();
+        return Ok(());
+    }
+
+    let completion = AtomicUsize::new(0);
+    post(core, func, &completion)?;
+    wait_for(&completion, 1);
+
+    Ok(())
+}
+
+/// Run `func` on all online cores, including the executing one, one after the other in order of
+/// their IDs.
+///
+/// # Panics
+///
+/// - In debug builds, if IRQs are masked: the targets could be waiting for this core as well.
+pub fn call_on_all(func: fn()) -> Result<(), Error> {
+    for core in (0..board::cpu::NUM_CORES).filter(|x| is_online(*x)) {
+        call_on(core, func)?;
+    }
+
+    Ok(())
+}
+
+/// Print the state of every online core, as seen by the core itself.
+///
+/// # Panics
+///
+/// - In debug builds, if IRQs are masked.
+pub fn print_cores() {
+    if let Err(x) = call_on_all(print_this_core) {
+        warn!("Failed to query the cores: {}", x);
+    }
+}
+
+fn print_this_core() {
+    let privilege_level = PrivilegeLevel::current();
+
+    info!(
+        "      Core {} | {} - {} | SP {:#018x} | task '{}'",
+        arch::cpu::core_id(),
+        privilege_level.kind(),
+        privilege_level.name(),
+        arch::cpu::stack_pointer(),
+        task::current()
+    );
+}
+
+/// Run `func` on all other online cores, without waiting for it to return.
+///
+/// Meant for when waiting is not an option, such as a panic: cores whose queue is full, or which
+/// can't be signaled, are skipped. Returns the number of cores `func` was sent to.
+pub fn call_on_others_nowait(func: fn()) -> usize {
+    let this_core = arch::cpu::core_id();
+    let call = CrossCall {
+        func,
+        completion: None,
+    };
+
+    (0..board::cpu::NUM_CORES)
+        .filter(|x| *x != this_core && is_online(*x))
+        .filter(|x| try_enqueue(*x, call) && interrupt::controller().send_ipi(*x).is_ok())
+        .count()
+}
+
+/// Queue a call on a core, unless its queue is full.
+fn try_enqueue(core: usize, call: CrossCall) -> bool {
+    PENDING_CALLS.for_core(core).lock(|calls| {
+        match calls.iter_mut().find(|x| x.is_none()) {
+            None => false,
+            Some(slot) => {
+                *slot = Some(call);
+                true
+            }
+        }
+    })
+}
+
+/// Queue `func` on a core, and raise its IPI.
+///
+/// # Panics
+///
+/// - If the IPI can't be sent, since the queued call would outlive `completion`.
+fn post(core: usize, func: fn(), completion: &AtomicUsize) -> Result<(), Error> {
+    if !is_online(core) {
+        return Err("Core is not online".into());
+    }
+
+    let call = CrossCall {
+        func,
+        completion: Some(completion),
+    };
+
+    // Wait for a free slot. The target empties its queue as soon as it takes the IPI.
+    while !try_enqueue(core, call) {
+        arch::cpu::nop();
+    }
+
+    if let Err(x) = interrupt::controller().send_ipi(core) {
+        panic!("Failed to send IPI to core {}: {}", core, x);
+    }
+
+    Ok(())
+}
+
+fn wait_for(completion: &AtomicUsize, count: usize) {
+    debug_assert!(
+        !arch::interrupt::local_irq_is_masked(),
+        "Waiting for a cross-core call with IRQs masked"
+    );
+
+    sync::spin_while(|| completion.load(Ordering::Acquire) < count);
+}
@@ -1,3 +1,9 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
 use crate::arch;
 
 pub struct Mutex<T>(arch::sync::Mutex<T>) where T: ?Sized;
@@ -8,11 +14,138 @@ impl<T> Mutex<T> {
         Self(arch::sync::Mutex::new(data))
     }
 
-    pub fn lock<'a, R>(&'a self, f: impl FnOnce(&'a mut T) -> R) -> R {
+    /// Run `f` with exclusive access to the data, spinning until the lock is available.
+    ///
+    /// # Panics
+    ///
+    /// - In debug builds, if the executing core already holds the lock.
+    #[track_caller]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
         self.0.lock(f)
     }
 }
 
+/// A [`Mutex`] that masks IRQs on the executing core while it is held.
+///
+/// Use it for data that is accessed from both thread and IRQ context: with a plain [`Mutex`], an
+/// IRQ handler that tries to take the lock from the holder's core would spin forever.
+pub struct IrqSafeMutex<T>(Mutex<T>) where T: ?Sized;
+
+impl<T> IrqSafeMutex<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self(Mutex::new(data))
+    }
+
+    /// Run `f` with IRQs masked and exclusive access to the data.
+    ///
+    /// The previous IRQ mask state is restored afterwards, so locks can be nested.
+    #[track_caller]
+    pub fn lock<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let saved = arch::interrupt::local_irq_mask_save();
+        let result = self.0.lock(f);
+        arch::interrupt::local_irq_restore(saved);
+
+        result
+    }
+}
+
+/// A reader-writer lock, for data that is read often and written rarely.
+///
+/// IRQs are masked on the executing core while the lock is held, so it can be shared with IRQ
+/// handlers. Readers must not nest: a writer waiting on another core would deadlock them.
+pub struct RwLock<T>(arch::sync::RwLock<T>) where T: ?Sized;
+
+impl<T> RwLock<T> {
+    /// Create an instance.
+    pub const fn new(data: T) -> Self {
+        Self(arch::sync::RwLock::new(data))
+    }
+
+    /// Run `f` with shared access to the data.
+    #[track_caller]
+    pub fn read<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        let saved = arch::interrupt::local_irq_mask_save();
+        let result = self.0.read(f);
+        arch::interrupt::local_irq_restore(saved);
+
+        result
+    }
+
+    /// Run `f` with exclusive access to the data.
+    #[track_caller]
+    pub fn write<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        let saved = arch::interrupt::local_irq_mask_save();
+        let result = self.0.write(f);
+        arch::interrupt::local_irq_restore(saved);
+
+        result
+    }
+}
+
+/// A cell that is written at most once, after which it can be read without locking.
+pub struct OnceCell<T> {
+    initialized: AtomicBool,
+    init_lock: Mutex<()>,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T> Send for OnceCell<T> where T: Send {}
+unsafe impl<T> Sync for OnceCell<T> where T: Send + Sync {}
+
+impl<T> OnceCell<T> {
+    /// Create an empty instance.
+    pub const fn new() -> Self {
+        Self {
+            initialized: AtomicBool::new(false),
+            init_lock: Mutex::new(()),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// The value, if the cell has been initialized.
+    pub fn get(&self) -> Option<&T> {
+        if self.initialized.load(Ordering::Acquire) {
+            // The value is never written again once it is initialized.
+            Some(unsafe { (*self.value.get()).assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    /// Initialize the cell with `value`.
+    ///
+    /// Hands `value` back if the cell was already initialized.
+    pub fn set(&self, value: T) -> Result<(), T> {
+        let mut value = Some(value);
+        self.get_or_init(|| value.take().unwrap());
+
+        match value {
+            None => Ok(()),
+            Some(value) => Err(value),
+        }
+    }
+
+    /// The value, initializing the cell with `f` if it is empty.
+    ///
+    /// If several cores race to initialize the cell, only one of them runs `f`.
+    pub fn get_or_init(&self, f: impl FnOnce() -> T) -> &T {
+        if let Some(value) = self.get() {
+            return value;
+        }
+
+        self.init_lock.lock(|_| {
+            if !self.initialized.load(Ordering::Acquire) {
+                unsafe { (*self.value.get()).write(f()) };
+                self.initialized.store(true, Ordering::Release);
+            }
+        });
+
+        // Initialized above, either by this core or by the one that won the race.
+        self.get().unwrap()
+    }
+}
+
 pub fn spin_while(cond: impl Fn() -> bool) {
     while cond() {
         arch::cpu::nop();
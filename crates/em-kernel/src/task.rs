@@ -0,0 +1,21 @@
+//! Identifies what each core is working on.
+//!
+//! There is no scheduler yet, so every core runs a single task for as long as it is online.
+
+use core::cell::Cell;
+
+use crate::percpu;
+
+percpu! {
+    static CURRENT_TASK: Cell<&'static str> = Cell::new("boot");
+}
+
+/// Set the name of the task the executing core runs.
+pub fn set_current(name: &'static str) {
+    CURRENT_TASK.get().set(name);
+}
+
+/// The name of the task the executing core runs.
+pub fn current() -> &'static str {
+    CURRENT_TASK.get().get()
+}
@@ -1,10 +1,68 @@
 use core::time::Duration;
 
-use crate::arch;
+use crate::{arch, driver::DeviceDriver, error::Error, interrupt::{self, IrqHandler}, percpu, sync::IrqSafeMutex, warn};
+
+/// A callback run from interrupt context when a timeout expires.
+pub type TimeoutCallback = fn();
+
+/// The maximum number of timeouts that can be pending at once.
+const MAX_TIMEOUTS: usize = 16;
+
+#[derive(Copy, Clone)]
+struct Timeout {
+    deadline: Duration,
+    callback: TimeoutCallback,
+}
+
+/// The pending timeouts, multiplexed onto the single hardware timer.
+struct TimerQueue {
+    timeouts: [Option<Timeout>; MAX_TIMEOUTS],
+}
+
+impl TimerQueue {
+    const fn new() -> Self {
+        Self {
+            timeouts: [None; MAX_TIMEOUTS],
+        }
+    }
+
+    fn insert(&mut self, timeout: Timeout) -> Result<(), Error> {
+        match self.timeouts.iter_mut().find(|x| x.is_none()) {
+            None => Err("Too many pending timeouts".into()),
+            Some(slot) => {
+                *slot = Some(timeout);
+                Ok(())
+            }
+        }
+    }
+
+    /// The earliest deadline of all pending timeouts.
+    fn next_deadline(&self) -> Option<Duration> {
+        self.timeouts.iter().flatten().map(|x| x.deadline).min()
+    }
+
+    /// Removes the earliest timeout whose deadline is no later than `now`.
+    fn pop_expired(&mut self, now: Duration) -> Option<Timeout> {
+        let slot = self
+            .timeouts
+            .iter_mut()
+            .filter(|x| matches!(x, Some(timeout) if timeout.deadline <= now))
+            .min_by_key(|x| x.map(|timeout| timeout.deadline))?;
+
+        slot.take()
+    }
+}
+
+percpu! {
+    /// The timeouts of the executing core, whose timer is separate from those of the other cores.
+    static TIMER_QUEUE: IrqSafeMutex<TimerQueue> = IrqSafeMutex::new(TimerQueue::new());
+}
 
 pub struct TimeKeeper;
 
 impl TimeKeeper {
+    pub const NAME: &'static str = "ARM Generic Timer";
+
     /// Create an instance.
     pub const fn new() -> Self {
         Self
@@ -23,13 +81,105 @@ impl TimeKeeper {
     }
 
     /// Spin for a given duration.
+    ///
+    /// Prefer [`TimeKeeper::sleep_for`], unless IRQs can't be taken.
     pub fn spin_for(&self, duration: Duration) {
         arch::time::spin_for(duration)
     }
+
+    /// Park the executing core for a given duration.
+    pub fn sleep_for(&self, duration: Duration) {
+        self.sleep_until(self.uptime() + duration)
+    }
+
+    /// Park the executing core until the uptime reaches `deadline`.
+    ///
+    /// Falls back to spinning if IRQs are masked, since the timer could never wake the core.
+    pub fn sleep_until(&self, deadline: Duration) {
+        debug_assert!(!interrupt::in_irq_context(), "sleep_until called from IRQ context");
+
+        let saved = arch::interrupt::local_irq_mask_save();
+
+        if saved.is_masked() {
+            arch::interrupt::local_irq_restore(saved);
+            self.spin_for(deadline.saturating_sub(self.uptime()));
+            return;
+        }
+
+        // No callback is needed: the timer's IRQ alone wakes the core.
+        if let Err(x) = self.schedule(deadline, || {}) {
+            warn!("sleep_until: {}. Spinning instead", x);
+            arch::interrupt::local_irq_restore(saved);
+            self.spin_for(deadline.saturating_sub(self.uptime()));
+            return;
+        }
+
+        // IRQs stay masked while checking the deadline, so that the timer can't fire between the
+        // check and the `wfi`. A pending IRQ wakes the core regardless, and is taken as soon as
+        // IRQs are unmasked again.
+        while self.uptime() < deadline {
+            arch::cpu::wait_for_interrupt();
+            arch::interrupt::local_irq_unmask();
+            arch::interrupt::local_irq_mask();
+        }
+
+        arch::interrupt::local_irq_restore(saved);
+    }
+
+    /// Run `callback` from interrupt context on the executing core once `delay` has passed.
+    pub fn set_timeout_once(&self, delay: Duration, callback: TimeoutCallback) -> Result<(), Error> {
+        self.schedule(self.uptime() + delay, callback)
+    }
+
+    fn schedule(&self, deadline: Duration, callback: TimeoutCallback) -> Result<(), Error> {
+        TIMER_QUEUE.get().lock(|queue| {
+            queue.insert(Timeout { deadline, callback })?;
+            Self::arm(queue);
+            Ok(())
+        })
+    }
+
+    /// Program the hardware timer for the earliest pending timeout.
+    fn arm(queue: &TimerQueue) {
+        match queue.next_deadline() {
+            None => arch::time::clear_deadline(),
+            Some(deadline) => arch::time::set_deadline(deadline),
+        }
+    }
+}
+
+impl DeviceDriver for TimeKeeper {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    unsafe fn init(&self) -> Result<(), Error> {
+        // The firmware may leave the timer armed.
+        arch::time::clear_deadline();
+        Ok(())
+    }
+
+    fn irq_handler(&'static self) -> Option<&'static (dyn IrqHandler + Sync)> {
+        Some(self)
+    }
+}
+
+impl IrqHandler for TimeKeeper {
+    fn handle(&self) -> Result<(), Error> {
+        let now = self.uptime();
+
+        // Callbacks run without holding the queue, so that they can schedule new timeouts.
+        while let Some(timeout) = TIMER_QUEUE.get().lock(|queue| queue.pop_expired(now)) {
+            (timeout.callback)();
+        }
+
+        TIMER_QUEUE.get().lock(|queue| Self::arm(queue));
+        Ok(())
+    }
 }
 
 static TIME_MANAGER: TimeKeeper = TimeKeeper::new();
 
 pub fn keeper() -> &'static TimeKeeper {
     &TIME_MANAGER
-}
\ No newline at end of file
+}
@@ -0,0 +1 @@
+QEMU_MACHINE_ARGS="-M virt,virtualization=on -cpu cortex-a72 -smp 4 -m 1G"
\ No newline at end of file
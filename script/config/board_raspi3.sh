@@ -1 +1 @@
-QEMU_MACHINE_ARGS="-M raspi3"
\ No newline at end of file
+QEMU_MACHINE_ARGS="-M raspi3b -smp 4"
\ No newline at end of file
@@ -0,0 +1 @@
+QEMU_MACHINE_ARGS="-M raspi4b"
\ No newline at end of file